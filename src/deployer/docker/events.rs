@@ -1,11 +1,12 @@
+use super::ONE_OFF;
 use crate::{
-    config::{self, Connection, DeploymentEngine},
-    deployer,
+    config::{self, Connection, DeploymentEngine, RestartPolicy},
+    deployer, notifier,
 };
 use bollard::models::SystemEventsResponse;
 use bollard::{system::EventsOptions, Docker, API_DEFAULT_VERSION};
-use std::collections::HashMap;
-use tokio::{select, sync::broadcast::Receiver};
+use std::{collections::HashMap, time::Instant};
+use tokio::{select, sync::broadcast::Receiver, task, time};
 use tokio_stream::StreamExt;
 use tracing::{debug, error, info, info_span, instrument, warn};
 
@@ -18,7 +19,9 @@ pub async fn watch(mut stop: Receiver<()>) {
         ..Default::default()
     }));
 
+    let policy = restart_policy();
     let mut last_events = HashMap::<String, Action>::new();
+    let mut restarts = HashMap::<String, Vec<Instant>>::new();
 
     select! {
         _ = async {
@@ -38,16 +41,36 @@ pub async fn watch(mut stop: Receiver<()>) {
                 // Restart the service if it exited with a non-zero exit code.
                 // Any service that was killed will not be restarted. If there is no previous event
                 // and the service exited, it is assumed to be unintentional.
+                // One-off containers are expected to exit and get cleaned up by whatever ran them.
                 let exited_non_zero = matches!(event.action, Action::Exit { code } if code != 0);
-                if exited_non_zero && (previous.is_none() || matches!(previous, Some(event) if event != &Action::Kill)) {
-                    warn!(parent: &span, id = %event.id, "container exited unexpectedly");
-                    match deployer::instance().start(&event.id).await {
-                        Ok(_) => { info!(parent: &span, id = %event.id, "restarted container"); },
-                        Err(e) => { error!(parent: &span, error = %e, "failed to restart container"); },
+                if exited_non_zero && !event.one_off && (previous.is_none() || matches!(previous, Some(event) if event != &Action::Kill)) {
+                    warn!(parent: &span, id = %event.id, service = %event.service, "container exited unexpectedly");
+
+                    // Only count the restarts within the window
+                    let recent = restarts.entry(event.id.clone()).or_default();
+                    recent.retain(|at| at.elapsed() < policy.window());
+
+                    let count = recent.len() as u32;
+                    if count >= policy.max_restarts {
+                        error!(parent: &span, id = %event.id, service = %event.service, "container is crash-looping, leaving it stopped");
+                        notifier::notify(notifier::Event::crash_loop(&event.service, count)).await;
+                        restarts.remove(&event.id);
+                    } else {
+                        recent.push(Instant::now());
+
+                        let delay = policy.backoff(count);
+                        info!(parent: &span, id = %event.id, ?delay, "restarting container");
+                        task::spawn(restart(event.id.clone(), delay));
                     }
                 }
 
-                last_events.insert(event.id, event.action);
+                // Destroyed containers can never produce another event
+                if event.action == Action::Destroy {
+                    last_events.remove(&event.id);
+                    restarts.remove(&event.id);
+                } else {
+                    last_events.insert(event.id, event.action);
+                }
             }
 
             Ok::<_, bollard::errors::Error>(())
@@ -58,6 +81,24 @@ pub async fn watch(mut stop: Receiver<()>) {
     }
 }
 
+/// Restart a container after waiting for its backoff delay
+#[instrument]
+async fn restart(id: String, delay: time::Duration) {
+    time::sleep(delay).await;
+
+    match deployer::instance().start(&id).await {
+        Ok(_) => info!("restarted container"),
+        Err(e) => error!(error = %e, "failed to restart container"),
+    }
+}
+
+/// Get the policy for restarting containers that exit unexpectedly
+fn restart_policy() -> &'static RestartPolicy {
+    match &config::instance().deployment.engine {
+        DeploymentEngine::Docker { restart, .. } => restart,
+    }
+}
+
 /// Create a new docker client. This is infallible as all the validation has already occurred.
 fn get_client() -> Docker {
     // Get the config
@@ -94,11 +135,13 @@ fn get_client() -> Docker {
     }
 }
 
-/// Get the filters for the events to listen to. Only listens for local container changes.
+/// Get the filters for the events to listen to. Only listens for changes to local containers
+/// managed by WaffleMaker.
 fn event_filters() -> HashMap<&'static str, Vec<&'static str>> {
     let mut filters = HashMap::new();
     filters.insert("scope", vec!["local"]);
     filters.insert("type", vec!["container"]);
+    filters.insert("label", vec!["wafflemaker.managed=true"]);
     filters
 }
 
@@ -145,16 +188,28 @@ impl Action {
 struct Event {
     action: Action,
     id: String,
+    service: String,
+    one_off: bool,
 }
 
 impl Event {
     fn new(source: SystemEventsResponse) -> Event {
         let actor = source.actor.unwrap();
-        let action = Action::new(source.action.unwrap(), actor.attributes.unwrap());
+        let attributes = actor.attributes.unwrap();
+
+        // Container labels are included in the attributes
+        let service = attributes
+            .get("wafflemaker.service")
+            .cloned()
+            .unwrap_or_default();
+        let one_off = attributes.get("wafflemaker.kind").map(String::as_str) == Some(ONE_OFF);
+        let action = Action::new(source.action.unwrap(), attributes);
 
         Event {
             action,
             id: actor.id.unwrap(),
+            service,
+            one_off,
         }
     }
 
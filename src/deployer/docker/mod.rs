@@ -1,37 +1,64 @@
-use super::{CreateOpts, Deployer, Result};
-use crate::config::Connection;
+use super::{error::Error, CreateOpts, Deployer, Exec, ExecOutput, HistoryEntry, LogOpts, Result};
+use crate::{
+    config::Connection,
+    service::{self, HealthCheck, Resources, Volume},
+};
 use async_trait::async_trait;
 use bollard::{
-    container::{Config as CreateContainerConfig, NetworkingConfig, RemoveContainerOptions},
+    container::{
+        Config as CreateContainerConfig, ListContainersOptions, LogOutput, LogsOptions,
+        NetworkingConfig, RemoveContainerOptions, WaitContainerOptions,
+    },
     errors::Error as BollardError,
+    exec::{CreateExecOptions, ResizeExecOptions, StartExecResults},
     image::CreateImageOptions,
-    models::{EndpointSettings, HostConfig},
+    models::{
+        ContainerSummaryInner, EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig, Mount,
+        MountTmpfsOptions, MountTypeEnum,
+    },
+    volume::CreateVolumeOptions,
     Docker as Bollard, API_DEFAULT_VERSION,
 };
-use futures::stream::StreamExt;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
 use rand::{distributions::Alphanumeric, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use reqwest::Client;
 use sled::{Config, Db, Mode};
-use std::{collections::HashMap, fs, path::Path};
-use tokio::sync::broadcast::Receiver;
-use tracing::{debug, error, info, instrument};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::{net::TcpStream, sync::broadcast::Sender};
+use tracing::{debug, error, info, instrument, warn};
 
 mod events;
+mod reconcile;
+
+/// The number of routing generations a service cycles through
+const GENERATIONS: u64 = 100;
+
+/// The kind of containers that run to completion, like jobs and hooks
+const ONE_OFF: &str = "one-off";
 
 #[derive(Debug)]
 pub struct Docker {
     instance: Bollard,
+    http: Client,
     state: Db,
     network: String,
     network_config: NetworkingConfig<String>,
     dns: String,
+    bind_directories: Vec<PathBuf>,
 }
 
 impl Docker {
     /// Connect to a new docker instance
     #[instrument(
         name = "docker",
-        skip(connection, endpoint, path),
+        skip(connection, endpoint, path, bind_directories),
         fields(
             connection = connection.kind(),
             endpoint = endpoint.as_ref(),
@@ -39,6 +66,7 @@ impl Docker {
             network = network.as_ref(),
         )
     )]
+    #[allow(clippy::too_many_arguments)]
     pub async fn new<S: AsRef<str>, P: AsRef<Path>>(
         connection: &Connection,
         endpoint: S,
@@ -46,7 +74,8 @@ impl Docker {
         dns_server: &str,
         network: S,
         path: P,
-        stop: Receiver<()>,
+        bind_directories: &[PathBuf],
+        stop: Sender<()>,
     ) -> Result<Self> {
         let endpoint = endpoint.as_ref();
         let path = path.as_ref();
@@ -76,7 +105,8 @@ impl Docker {
         debug!("created docker connection");
 
         // Create the database folder if not exists
-        if !path.exists() {
+        let missing = !path.exists();
+        if missing {
             fs::create_dir_all(path)?;
         }
 
@@ -106,70 +136,243 @@ impl Docker {
             },
         };
 
-        tokio::task::spawn(events::watch(stop));
-
-        Ok(Self {
+        let docker = Self {
             instance,
+            http: Client::new(),
             state,
             network: network_name,
             network_config,
             dns: dns_server.to_owned(),
-        })
+            // Bind sources are resolved before being checked, so the directories must be too
+            bind_directories: bind_directories
+                .iter()
+                .map(|d| std::fs::canonicalize(d).unwrap_or_else(|_| d.to_owned()))
+                .collect(),
+        };
+
+        if missing {
+            warn!("state database is missing, recovering from container labels");
+        }
+        docker.recover().await?;
+
+        tokio::task::spawn(events::watch(stop.subscribe()));
+        tokio::task::spawn(reconcile::watch(stop.subscribe()));
+
+        Ok(docker)
     }
 
-    /// Convert a deployment name to a docker id
-    fn id_from_name<S: AsRef<str>>(&self, name: S) -> Result<String> {
-        let tree = self.state.open_tree(name.as_ref())?;
-        get_string(&tree, "id").transpose().unwrap()
+    /// List all the containers managed by WaffleMaker, including stopped ones
+    async fn list_managed(&self) -> Result<Vec<ContainerSummaryInner>> {
+        let mut filters = HashMap::new();
+        filters.insert("label", vec!["wafflemaker.managed=true"]);
+
+        let containers = self
+            .instance
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await?;
+        Ok(containers)
     }
-}
 
-#[async_trait]
-impl Deployer for Docker {
-    #[instrument(skip(self))]
-    async fn test(&self) -> Result<()> {
-        let info = self.instance.info().await?;
-        let id = info.id.unwrap_or_default();
-        info!(id = %id, "connected to docker");
+    /// Rebuild the state from the labels of the managed containers for any service whose tracked
+    /// containers no longer match what actually exists. Any containers that aren't recovered are
+    /// left for the reconciler to clean up.
+    async fn recover(&self) -> Result<()> {
+        let mut services = HashMap::<String, Vec<ContainerSummaryInner>>::new();
+        for container in self.list_managed().await? {
+            if label(&container, "wafflemaker.kind") == Some(ONE_OFF) {
+                continue;
+            }
+
+            if let Some(service) = label(&container, "wafflemaker.service") {
+                services
+                    .entry(service.to_owned())
+                    .or_default()
+                    .push(container);
+            }
+        }
+
+        for (service, containers) in services {
+            let tree = self.state.open_tree(&service)?;
+            let tracked = get_ids(&tree, "id")?;
+            let consistent = !tracked.is_empty()
+                && tracked
+                    .iter()
+                    .all(|id| containers.iter().any(|c| c.id.as_ref() == Some(id)));
+            if consistent {
+                continue;
+            }
+
+            // Use the newest deployment, giving priority to ones that are still running
+            let newest = containers
+                .iter()
+                .max_by_key(|c| {
+                    (
+                        c.state.as_deref() == Some("running"),
+                        c.created.unwrap_or_default(),
+                    )
+                })
+                .unwrap();
+            let deployment = |c: &ContainerSummaryInner| {
+                (
+                    label(c, "wafflemaker.commit").map(str::to_owned),
+                    label(c, "wafflemaker.image").map(str::to_owned),
+                )
+            };
+            let current = deployment(newest);
+
+            let ids = containers
+                .iter()
+                .filter(|c| deployment(c) == current)
+                .filter_map(|c| c.id.clone())
+                .collect::<Vec<_>>();
+            tree.remove("previous")?;
+            set_ids(&tree, "id", &ids)?;
+
+            info!(
+                %service,
+                commit = current.0.as_deref().unwrap_or_default(),
+                image = current.1.as_deref().unwrap_or_default(),
+                "recovered {} replica(s) from container labels",
+                ids.len()
+            );
+        }
 
         Ok(())
     }
 
-    #[instrument(skip(self))]
-    async fn list(&self) -> Result<HashMap<String, String>> {
+    /// Convert a volume into its mount specification, creating it if necessary
+    async fn mount(&self, service: &str, volume: &Volume) -> Result<Mount> {
+        let mount = match volume {
+            Volume::Named {
+                name,
+                target,
+                read_only,
+                ..
+            } => {
+                let mut labels = HashMap::new();
+                labels.insert("wafflemaker.managed", "true");
+                labels.insert("wafflemaker.service", service);
+
+                // Creating a volume that already exists is a no-op
+                let name = volume_name(service, name);
+                self.instance
+                    .create_volume(CreateVolumeOptions {
+                        name: name.as_str(),
+                        driver: "local",
+                        labels,
+                        ..Default::default()
+                    })
+                    .await?;
+                debug!(%name, "created volume");
+
+                Mount {
+                    target: Some(target.to_owned()),
+                    source: Some(name),
+                    typ: Some(MountTypeEnum::VOLUME),
+                    read_only: Some(*read_only),
+                    ..Default::default()
+                }
+            }
+            Volume::Bind { source, target } => {
+                if !source.is_absolute() || source.components().any(|c| c == Component::ParentDir) {
+                    return Err(Error::DisallowedBind(source.to_owned()));
+                }
+
+                // Follow any symlinks so they can't be used to escape the allowed directories
+                let resolved = tokio::fs::canonicalize(source)
+                    .await
+                    .map_err(|_| Error::DisallowedBind(source.to_owned()))?;
+                if !self
+                    .bind_directories
+                    .iter()
+                    .any(|d| resolved.starts_with(d))
+                {
+                    return Err(Error::DisallowedBind(source.to_owned()));
+                }
+
+                Mount {
+                    target: Some(target.to_owned()),
+                    source: Some(resolved.display().to_string()),
+                    typ: Some(MountTypeEnum::BIND),
+                    read_only: Some(true),
+                    ..Default::default()
+                }
+            }
+            Volume::Tmpfs { target, size } => Mount {
+                target: Some(target.to_owned()),
+                typ: Some(MountTypeEnum::TMPFS),
+                tmpfs_options: Some(MountTmpfsOptions {
+                    size_bytes: size.map(|s| s.0 as i64),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        };
+
+        Ok(mount)
+    }
+
+    /// Get the ids stored under a key for every service that has any
+    fn list_ids(&self, key: &str) -> Result<HashMap<String, Vec<String>>> {
         let mut mapping = HashMap::new();
         for tree_name in self.state.tree_names() {
-            let id = get_string(&self.state.open_tree(&tree_name)?, "id")?;
-            if let Some(id) = id {
-                let name = String::from_utf8(tree_name.as_ref().to_vec()).unwrap();
-                mapping.insert(name, id);
-            } else {
+            let ids = get_ids(&self.state.open_tree(&tree_name)?, key)?;
+            if ids.is_empty() {
                 continue;
             }
+
+            let name = String::from_utf8(tree_name.as_ref().to_vec()).unwrap();
+            mapping.insert(name, ids);
         }
 
         Ok(mapping)
     }
 
-    #[instrument(skip(self))]
-    async fn service_id(&self, name: &str) -> Result<Option<String>> {
-        let tree = self.state.open_tree(name)?;
-        get_string(&tree, "id")
-    }
+    /// Stop tracking a container. If it was the last replica of a service's current deployment,
+    /// the previous deployment (if any) becomes the current deployment again.
+    fn untrack(&self, id: &str) -> Result<()> {
+        for tree_name in self.state.tree_names() {
+            let tree = self.state.open_tree(&tree_name)?;
 
-    #[instrument(
-        skip(self, options),
-        fields(
-            name = %options.name,
-            web = %options.routing.is_some(),
-            routing = ?options.routing,
-            image = %options.image),
-        )
-    ]
-    async fn create(&self, options: CreateOpts) -> Result<String> {
-        let tree = self.state.open_tree(&options.name)?;
+            let mut previous = get_ids(&tree, "previous")?;
+            if previous.iter().any(|p| p == id) {
+                previous.retain(|p| p != id);
+                set_ids(&tree, "previous", &previous)?;
+                continue;
+            }
 
-        tree.insert("image", options.image.as_str())?;
+            let mut current = get_ids(&tree, "id")?;
+            if current.iter().any(|c| c == id) {
+                current.retain(|c| c != id);
+                if current.is_empty() {
+                    tree.remove("previous")?;
+                    set_ids(&tree, "id", &previous)?;
+                } else {
+                    set_ids(&tree, "id", &current)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the configuration for a service's container, pulling its image and preparing its
+    /// volumes
+    async fn container_config(&self, options: CreateOpts) -> Result<CreateContainerConfig<String>> {
+        // Prepare the volumes, this is done first so an invalid bind mount fails fast
+        let mut mounts = Vec::new();
+        for volume in &options.volumes {
+            mounts.push(self.mount(&options.name, volume).await?);
+            debug!(
+                r#type = volume.name(),
+                target = volume.target(),
+                "added volume"
+            );
+        }
 
         let environment = options
             .environment
@@ -178,6 +381,13 @@ impl Deployer for Docker {
             .collect();
 
         let mut labels = HashMap::new();
+        labels.insert("wafflemaker.managed".to_string(), "true".to_string());
+        labels.insert("wafflemaker.service".to_string(), options.name.clone());
+        labels.insert("wafflemaker.commit".to_string(), options.commit.clone());
+        labels.insert(
+            "wafflemaker.image".to_string(),
+            format!("{}:{}", &options.image, &options.tag),
+        );
 
         // Pull the image
         info!(
@@ -200,6 +410,17 @@ impl Deployer for Docker {
             }
         }
 
+        // Determine the service port, used for load balancing and health checks
+        let image = self
+            .instance
+            .inspect_image(&format!("{}:{}", &options.image, &options.tag))
+            .await?;
+        let exposed_port = image
+            .config
+            .and_then(|c| c.exposed_ports)
+            .as_ref()
+            .and_then(first_port);
+
         if let Some(routing) = &options.routing {
             let suffix = ChaCha20Rng::from_rng(rand::thread_rng())
                 .unwrap()
@@ -214,7 +435,17 @@ impl Deployer for Docker {
                 Some(p) => format!("Host(`{}`) && PathPrefix(`{}`)", routing.domain, p),
                 None => format!("Host(`{}`)", routing.domain),
             };
+
+            // Traefik orders routers by the length of their rule by default. That ordering is
+            // kept between services, while a newer deployment of a service takes precedence over
+            // the one it is replacing when both are running.
+            let generation = next_generation(&self.state.open_tree(&options.name)?)?;
+            let priority = rule.len() as u64 * GENERATIONS + generation;
             labels.insert(format!("traefik.http.routers.{}.rule", router_name), rule);
+            labels.insert(
+                format!("traefik.http.routers.{}.priority", router_name),
+                priority.to_string(),
+            );
 
             // Add path prefix middleware if necessary
             if let Some(path) = &routing.path {
@@ -241,32 +472,16 @@ impl Deployer for Docker {
 
             debug!("added routing labels");
 
-            // Determine the service port
-            info!("attempting to determine service port for load balancing...");
-            let image = self
-                .instance
-                .inspect_image(&format!("{}:{}", &options.image, &options.tag))
-                .await?;
-            if let Some(image_config) = image.config {
-                if let Some(ports) = image_config.exposed_ports {
-                    if !ports.is_empty() {
-                        // The port specification is in the format <port>/<tcp|udp|sctp>, but we
-                        // only care about the port itself, the protocol is assumed to be TCP
-                        let mut port = ports.keys().take(1).next().cloned().unwrap();
-                        let proto_idx = port.find('/').unwrap();
-                        port.truncate(proto_idx);
-
-                        info!("found port {} for service", port);
-
-                        labels.insert(
-                            format!(
-                                "traefik.http.services.{}.loadbalancer.server.port",
-                                router_name
-                            ),
-                            port,
-                        );
-                    }
-                }
+            if let Some(port) = &exposed_port {
+                info!("found port {} for service", port);
+
+                labels.insert(
+                    format!(
+                        "traefik.http.services.{}.loadbalancer.server.port",
+                        router_name
+                    ),
+                    port.clone(),
+                );
             }
         }
 
@@ -276,8 +491,31 @@ impl Deployer for Docker {
             options.routing.is_some().to_string(),
         );
 
-        let config = CreateContainerConfig {
+        // Traefik ignores containers until they report as healthy, so run exec checks through
+        // Docker to prevent routing traffic to a deployment that isn't ready. HTTP and TCP
+        // checks are run by the agent as the image may not have any tools to run them with.
+        let healthcheck = options
+            .health
+            .as_ref()
+            .and_then(|health| match &health.check {
+                HealthCheck::Exec { command } => Some(HealthConfig {
+                    test: Some(
+                        std::iter::once("CMD".to_string())
+                            .chain(command.iter().cloned())
+                            .collect(),
+                    ),
+                    interval: Some(nanoseconds(health.interval())),
+                    timeout: Some(nanoseconds(health.timeout())),
+                    retries: Some(health.retries as i64),
+                    start_period: Some(nanoseconds(health.start_period())),
+                }),
+                _ => None,
+            });
+
+        Ok(CreateContainerConfig {
             image: Some(format!("{}:{}", &options.image, &options.tag)),
+            cmd: options.command,
+            healthcheck,
             env: Some(environment),
             attach_stderr: Some(true),
             attach_stdout: Some(true),
@@ -285,17 +523,144 @@ impl Deployer for Docker {
             networking_config: Some(self.network_config.clone()),
             host_config: Some(HostConfig {
                 dns: Some(vec![self.dns.clone()]),
+                memory: options.resources.memory.map(|m| m.0 as i64),
+                memory_reservation: options.resources.memory_reservation.map(|m| m.0 as i64),
+                cpu_shares: options.resources.cpu_shares,
+                cpu_period: options.resources.cpu_period,
+                cpu_quota: options.resources.cpu_quota,
+                pids_limit: options.resources.pids,
+                mounts: Some(mounts),
                 ..Default::default()
             }),
             ..Default::default()
-        };
+        })
+    }
+}
+
+#[async_trait]
+impl Deployer for Docker {
+    #[instrument(skip(self))]
+    async fn test(&self) -> Result<()> {
+        let info = self.instance.info().await?;
+        let id = info.id.unwrap_or_default();
+        info!(id = %id, "connected to docker");
+
+        Ok(())
+    }
+
+    fn tree(&self, name: &str) -> Result<sled::Tree> {
+        Ok(self.state.open_tree(name)?)
+    }
+
+    #[instrument(skip(self))]
+    async fn flush(&self) -> Result<()> {
+        let flushed = self.state.flush_async().await?;
+        debug!(bytes = %flushed, "flushed state database");
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn list(&self) -> Result<HashMap<String, Vec<String>>> {
+        self.list_ids("id")
+    }
+
+    #[instrument(skip(self))]
+    async fn list_previous(&self) -> Result<HashMap<String, Vec<String>>> {
+        self.list_ids("previous")
+    }
+
+    #[instrument(skip(self))]
+    async fn managed(&self) -> Result<HashMap<String, String>> {
+        let mapping = self
+            .list_managed()
+            .await?
+            .into_iter()
+            .filter_map(|c| {
+                let service = c.labels?.remove("wafflemaker.service")?;
+                Some((c.id?, service))
+            })
+            .collect();
+        Ok(mapping)
+    }
+
+    #[instrument(skip(self))]
+    async fn service_ids(&self, name: &str) -> Result<Vec<String>> {
+        let tree = self.state.open_tree(name)?;
+        get_ids(&tree, "id")
+    }
+
+    #[instrument(
+        skip(self, options),
+        fields(
+            name = %options.name,
+            web = %options.routing.is_some(),
+            routing = ?options.routing,
+            image = %options.image),
+        )
+    ]
+    async fn create(&self, options: CreateOpts) -> Result<Vec<String>> {
+        let tree = self.state.open_tree(&options.name)?;
+
+        tree.insert("image", options.image.as_str())?;
+
+        let replicas = options.replicas;
+        let config = self.container_config(options).await?;
+
+        // Create each of the replicas, sharing the same routing labels so they get registered
+        // under the same load balancer
+        let mut ids: Vec<String> = Vec::new();
+        for replica in 1..=replicas {
+            let result = match self
+                .instance
+                .create_container::<&str, _>(None, config.clone())
+                .await
+            {
+                Ok(result) => result,
+                Err(e) => {
+                    // Nothing tracks the replicas that were already created, so clean them up
+                    for id in &ids {
+                        if let Err(e) = self.delete(id).await {
+                            warn!(%id, error = %e, "failed to remove partially created replica");
+                        }
+                    }
+                    return Err(e.into());
+                }
+            };
+            debug!(id = %result.id, "created replica {}/{}", replica, replicas);
+
+            ids.push(result.id);
+        }
+
+        // Keep track of the previous deployment in case the new one needs to be rolled back
+        let previous = get_ids(&tree, "id")?;
+        if !previous.is_empty() {
+            set_ids(&tree, "previous", &previous)?;
+        }
+        set_ids(&tree, "id", &ids)?;
+
+        Ok(ids)
+    }
+
+    #[instrument(
+        skip(self, options),
+        fields(
+            name = %options.name,
+            image = %options.image,
+        )
+    )]
+    async fn create_one_off(&self, options: CreateOpts) -> Result<String> {
+        let mut config = self.container_config(options).await?;
+
+        // Mark the container so it doesn't get restarted or recovered as part of a deployment
+        if let Some(labels) = config.labels.as_mut() {
+            labels.insert("wafflemaker.kind".to_string(), ONE_OFF.to_string());
+        }
 
         let result = self
             .instance
             .create_container::<&str, _>(None, config)
             .await?;
-
-        tree.insert("id", result.id.as_str())?;
+        debug!(id = %result.id, "created one-off container");
 
         Ok(result.id)
     }
@@ -311,6 +676,229 @@ impl Deployer for Docker {
         Ok(())
     }
 
+    #[instrument(skip(self, check), fields(check = check.name()))]
+    async fn healthy(&self, id: &str, check: &HealthCheck) -> Result<bool> {
+        let info = self.instance.inspect_container(id, None).await?;
+
+        // A stopped container can never be healthy
+        let state = info.state.unwrap_or_default();
+        if !state.running.unwrap_or_default() {
+            debug!("container is not running");
+            return Ok(false);
+        }
+
+        // Once docker has settled on the health of a container it tracks, that takes precedence
+        // over checking directly. While it is still starting, the check is run here instead.
+        match state.health.and_then(|h| h.status) {
+            Some(HealthStatusEnum::HEALTHY) => {
+                debug!("docker reports container as healthy");
+                return Ok(true);
+            }
+            Some(HealthStatusEnum::UNHEALTHY) => {
+                debug!("docker reports container as unhealthy");
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        let ip = info
+            .network_settings
+            .and_then(|s| s.networks)
+            .and_then(|mut n| n.remove(&self.network))
+            .and_then(|n| n.ip_address)
+            .unwrap_or_default();
+        let exposed_port = info
+            .config
+            .and_then(|c| c.exposed_ports)
+            .as_ref()
+            .and_then(first_port);
+        let port = |configured: &Option<u16>| {
+            configured
+                .map(|p| p.to_string())
+                .or_else(|| exposed_port.clone())
+        };
+
+        let healthy = match check {
+            HealthCheck::Http {
+                path,
+                port: configured,
+            } => {
+                let port = match port(configured) {
+                    Some(p) => p,
+                    None => {
+                        warn!("unable to determine port to check");
+                        return Ok(false);
+                    }
+                };
+                let path = path.strip_prefix('/').unwrap_or(path);
+
+                let url = format!("http://{}:{}/{}", ip, port, path);
+                match self.http.get(&url).send().await {
+                    Ok(response) => response.status().is_success(),
+                    Err(e) => {
+                        debug!(error = %e, "failed to send request");
+                        false
+                    }
+                }
+            }
+            HealthCheck::Tcp { port: configured } => {
+                let port = match port(configured) {
+                    Some(p) => p,
+                    None => {
+                        warn!("unable to determine port to check");
+                        return Ok(false);
+                    }
+                };
+
+                TcpStream::connect(format!("{}:{}", ip, port)).await.is_ok()
+            }
+            HealthCheck::Exec { command } => {
+                let exec = self
+                    .instance
+                    .create_exec(
+                        id,
+                        CreateExecOptions {
+                            cmd: Some(command.clone()),
+                            attach_stdout: Some(true),
+                            attach_stderr: Some(true),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                // Wait for the command to complete
+                if let StartExecResults::Attached { mut output, .. } =
+                    self.instance.start_exec(&exec.id, None).await?
+                {
+                    while let Some(line) = output.next().await {
+                        debug!("{}", line?);
+                    }
+                }
+
+                let result = self.instance.inspect_exec(&exec.id).await?;
+                result.exit_code == Some(0)
+            }
+        };
+
+        Ok(healthy)
+    }
+
+    #[instrument(skip(self))]
+    async fn exec(&self, id: &str, command: &[String], tty: bool) -> Result<Exec> {
+        let exec = self
+            .instance
+            .create_exec(
+                id,
+                CreateExecOptions {
+                    cmd: Some(command.to_vec()),
+                    attach_stdin: Some(true),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    tty: Some(tty),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        match self.instance.start_exec(&exec.id, None).await? {
+            StartExecResults::Attached { output, input } => {
+                let output = output
+                    .map(|chunk| {
+                        chunk
+                            .map(|output| match output {
+                                LogOutput::StdErr { message } => ExecOutput::Stderr(message),
+                                output => ExecOutput::Stdout(output.into_bytes()),
+                            })
+                            .map_err(Error::from)
+                    })
+                    .boxed();
+                debug!(exec = %exec.id, "started command");
+
+                Ok(Exec {
+                    id: exec.id,
+                    input,
+                    output,
+                })
+            }
+            StartExecResults::Detached => unreachable!("command was started attached"),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn resize_exec(&self, exec: &str, width: u16, height: u16) -> Result<()> {
+        self.instance
+            .resize_exec(exec, ResizeExecOptions { width, height })
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn exec_exit_code(&self, exec: &str) -> Result<Option<i64>> {
+        let result = self.instance.inspect_exec(exec).await?;
+        Ok(result.exit_code)
+    }
+
+    #[instrument(skip(self))]
+    async fn logs(&self, id: &str, options: LogOpts) -> Result<BoxStream<'static, Result<Bytes>>> {
+        // Ensure the container exists before starting the stream
+        self.instance.inspect_container(id, None).await?;
+
+        let stream = self
+            .instance
+            .logs(
+                id,
+                Some(LogsOptions {
+                    follow: options.follow,
+                    stdout: true,
+                    stderr: true,
+                    since: options.since.unwrap_or_default(),
+                    tail: options
+                        .tail
+                        .map(|t| t.to_string())
+                        .unwrap_or_else(|| "all".to_string()),
+                    ..Default::default()
+                }),
+            )
+            .map(|chunk| chunk.map(LogOutput::into_bytes).map_err(Error::from))
+            .boxed();
+
+        Ok(stream)
+    }
+
+    #[instrument(skip(self))]
+    async fn wait(&self, id: &str) -> Result<i64> {
+        let mut responses = self.instance.wait_container(
+            id,
+            Some(WaitContainerOptions {
+                condition: "not-running",
+            }),
+        );
+        if let Some(response) = responses.next().await {
+            return Ok(response?.status_code);
+        }
+
+        // The stream can end without a response if the container already exited
+        let info = self.instance.inspect_container(id, None).await?;
+        Ok(info.state.and_then(|s| s.exit_code).unwrap_or_default())
+    }
+
+    #[instrument(skip(self))]
+    async fn resources(&self, id: &str) -> Result<Resources> {
+        let info = self.instance.inspect_container(id, None).await?;
+        let host = info.host_config.unwrap_or_default();
+
+        // Docker reports limits that aren't set as zero
+        let limit = |value: Option<i64>| value.filter(|v| *v > 0);
+        Ok(Resources {
+            memory: limit(host.memory).map(|m| service::Bytes(m as u64)),
+            memory_reservation: limit(host.memory_reservation).map(|m| service::Bytes(m as u64)),
+            cpu_shares: limit(host.cpu_shares),
+            cpu_period: limit(host.cpu_period),
+            cpu_quota: limit(host.cpu_quota),
+            pids: limit(host.pids_limit),
+        })
+    }
+
     #[instrument(skip(self))]
     async fn ip(&self, id: &str) -> Result<String> {
         let info = self.instance.inspect_container(id, None).await?;
@@ -354,13 +942,66 @@ impl Deployer for Docker {
             }
         }
 
+        self.untrack(id)?;
+
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    async fn delete_volume(&self, service: &str, volume: &str) -> Result<()> {
+        let status = self
+            .instance
+            .remove_volume(&volume_name(service, volume), None)
+            .await;
+        if let Err(e) = status {
+            if !matches!(e, BollardError::DockerResponseNotFoundError { .. }) {
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, entry), fields(commit = %entry.commit))]
+    async fn record(&self, name: &str, mut entry: HistoryEntry) -> Result<u64> {
+        let tree = self.state.open_tree(history_tree(name))?;
+
+        // Versions are stored big-endian so the entries are kept in order
+        let version = match tree.last()? {
+            Some((key, _)) => version_from_key(&key) + 1,
+            None => 1,
+        };
+        entry.version = version;
+
+        let value = serde_json::to_vec(&entry).map_err(|e| Error::Serialization(e.into()))?;
+        tree.insert(version.to_be_bytes(), value)?;
+        debug!(version, "recorded deployment in history");
+
+        Ok(version)
+    }
+
+    #[instrument(skip(self))]
+    async fn history(&self, name: &str) -> Result<Vec<HistoryEntry>> {
+        let tree = self.state.open_tree(history_tree(name))?;
+
+        let mut entries = Vec::new();
+        for result in tree.iter() {
+            let (key, value) = result?;
+            let mut entry: HistoryEntry =
+                serde_json::from_slice(&value).map_err(|e| Error::Parsing(e.into()))?;
+            entry.version = version_from_key(&key);
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
     #[instrument(skip(self))]
     async fn delete_by_name(&self, name: &str) -> Result<()> {
-        let id = self.id_from_name(&name)?;
-        self.delete(&id).await?;
+        let tree = self.state.open_tree(name)?;
+        for id in get_ids(&tree, "id")? {
+            self.delete(&id).await?;
+        }
 
         // Remove the state for the deployment
         self.state.drop_tree(name)?;
@@ -377,6 +1018,85 @@ impl Drop for Docker {
     }
 }
 
+/// Get the value of a container's label
+fn label<'c>(container: &'c ContainerSummaryInner, key: &str) -> Option<&'c str> {
+    container
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(key))
+        .map(String::as_str)
+}
+
+/// Get the next routing generation of a service. The generation is added to the priority of
+/// its routers so a new deployment takes over from the previous one. Once it wraps around, the
+/// new deployment only takes over when the previous one is stopped.
+fn next_generation(tree: &sled::Tree) -> Result<u64> {
+    let previous = get_string(tree, "generation")?
+        .and_then(|g| g.parse::<u64>().ok())
+        .unwrap_or_default();
+    let generation = (previous + 1) % GENERATIONS;
+    tree.insert("generation", generation.to_string().as_str())?;
+
+    Ok(generation)
+}
+
+/// Convert a duration to nanoseconds as Docker expects
+fn nanoseconds(duration: Duration) -> i64 {
+    duration.as_nanos() as i64
+}
+
+/// Get the name of the tree holding a service's deployment history. This is kept separate
+/// from the service's state so it persists after the service is deleted.
+fn history_tree(service: &str) -> String {
+    format!("history:{}", service)
+}
+
+/// Convert a big-endian history key into its version
+fn version_from_key(key: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&key[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Get the name of a service's volume within Docker
+fn volume_name(service: &str, volume: &str) -> String {
+    format!("{}.{}", service.replace('/', "_"), volume)
+}
+
+/// Get the first port from a set of exposed ports. The port specification is in the format
+/// <port>/<tcp|udp|sctp>, but we only care about the port itself, the protocol is assumed to be TCP.
+fn first_port(ports: &HashMap<String, HashMap<(), ()>>) -> Option<String> {
+    let mut port = ports.keys().next().cloned()?;
+    if let Some(proto_idx) = port.find('/') {
+        port.truncate(proto_idx);
+    }
+
+    Some(port)
+}
+
+/// Retrieve a set of container IDs from a given key. The IDs are stored as a comma-separated list.
+fn get_ids<K: AsRef<[u8]>>(tree: &sled::Tree, key: K) -> Result<Vec<String>> {
+    Ok(get_string(tree, key)?
+        .map(|ids| {
+            ids.split(',')
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Save a set of container IDs to a given key, removing the key if there are none
+fn set_ids<K: AsRef<[u8]>>(tree: &sled::Tree, key: K, ids: &[String]) -> Result<()> {
+    if ids.is_empty() {
+        tree.remove(key)?;
+    } else {
+        tree.insert(key, ids.join(",").as_str())?;
+    }
+
+    Ok(())
+}
+
 /// Retrieve a string from a given key
 fn get_string<K: AsRef<[u8]>>(tree: &sled::Tree, key: K) -> Result<Option<String>> {
     Ok(tree
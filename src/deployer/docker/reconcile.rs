@@ -0,0 +1,181 @@
+use crate::{
+    config::{self, DeploymentEngine},
+    deployer::{self, Error, Outcome},
+    notifier::{self, Event, State},
+    processor::jobs::{self, UpdateService},
+    service::{registry::REGISTRY, Kind},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{select, sync::broadcast::Receiver, time};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Periodically converge the running containers with the desired state from the registry
+#[instrument(skip(stop))]
+pub async fn watch(mut stop: Receiver<()>) {
+    let period = match &config::instance().deployment.engine {
+        DeploymentEngine::Docker {
+            reconcile_interval, ..
+        } => Duration::from_secs(reconcile_interval.get()),
+    };
+
+    select! {
+        _ = async {
+            loop {
+                // Wait before the first run so everything has a chance to start up
+                time::sleep(period).await;
+
+                let mut redeployed = Vec::new();
+                let mut removed = Vec::new();
+                let state = match reconcile(&mut redeployed, &mut removed).await {
+                    Ok(_) if redeployed.is_empty() && removed.is_empty() => {
+                        debug!("state is consistent, nothing to do");
+                        continue;
+                    }
+                    Ok(_) => State::Success,
+                    Err(e) => {
+                        error!(error = %e, "failed to reconcile state");
+                        State::Failure(e.to_string())
+                    }
+                };
+
+                notifier::notify(Event::reconciliation(&redeployed, &removed, state)).await;
+            }
+        } => {}
+        _ = stop.recv() => {
+            info!("stopped reconciler");
+        }
+    }
+}
+
+/// Compare the registry, tracked state, and running containers, fixing any differences.
+/// Services with missing containers get redeployed and containers that are not tracked
+/// by any service get removed. Services with a job queued or running are left alone as the
+/// job may be in the middle of replacing their containers.
+async fn reconcile(redeployed: &mut Vec<String>, removed: &mut Vec<String>) -> Result<(), Error> {
+    let deployer = deployer::instance();
+
+    // The containers must be listed before checking which services are busy, otherwise a job
+    // could start and create containers that aren't tracked yet in between
+    let containers = deployer.managed().await?;
+    let busy = jobs::instance().busy();
+    let tracked = deployer.list().await?;
+    let previous = deployer.list_previous().await?;
+    let reg = REGISTRY.read().await.clone();
+
+    for (name, config) in reg.iter() {
+        // Jobs don't keep any containers around once they finish
+        if config.kind == Kind::Job || busy.contains(name) {
+            continue;
+        }
+
+        let ids = tracked.get(name).cloned().unwrap_or_default();
+        let stale = ids
+            .iter()
+            .filter(|id| !containers.contains_key(*id))
+            .collect::<Vec<_>>();
+        if !ids.is_empty() && stale.is_empty() {
+            continue;
+        }
+
+        // Don't continuously retry a configuration that failed to deploy
+        let history = deployer.history(name).await?;
+        if matches!(history.last(), Some(entry) if entry.outcome == Outcome::Failure) {
+            debug!(%name, "skipping service whose last deployment failed");
+            continue;
+        }
+
+        for id in stale {
+            deployer.delete(id).await?;
+        }
+
+        warn!(%name, "service is missing containers, redeploying");
+        jobs::dispatch(UpdateService::new(config.clone(), name.as_str().into()));
+        redeployed.push(name.clone());
+    }
+
+    for (id, service) in orphans(containers, &tracked, &previous, &busy) {
+        warn!(%id, %service, "removing orphaned container");
+        deployer.stop(&id).await?;
+        deployer.delete(&id).await?;
+        removed.push(format!("{} ({})", service, &id[..12]));
+    }
+
+    Ok(())
+}
+
+/// Find the containers that aren't part of any service's current or previous deployment.
+/// Containers of busy services are never orphaned as they may not be tracked yet.
+fn orphans(
+    containers: HashMap<String, String>,
+    tracked: &HashMap<String, Vec<String>>,
+    previous: &HashMap<String, Vec<String>>,
+    busy: &HashSet<String>,
+) -> Vec<(String, String)> {
+    let known = tracked
+        .values()
+        .chain(previous.values())
+        .flatten()
+        .collect::<HashSet<_>>();
+
+    let mut orphans = containers
+        .into_iter()
+        .filter(|(id, service)| !known.contains(id) && !busy.contains(service))
+        .collect::<Vec<_>>();
+    orphans.sort_unstable();
+    orphans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::orphans;
+    use std::collections::{HashMap, HashSet};
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_previous_deployment() {
+        let containers = [
+            ("blue-1", "wafflehacks/cms"),
+            ("blue-2", "wafflehacks/cms"),
+            ("green-1", "wafflehacks/cms"),
+            ("stray", "wafflehacks/cms"),
+        ]
+        .iter()
+        .map(|(id, service)| (id.to_string(), service.to_string()))
+        .collect();
+
+        let mut tracked = HashMap::new();
+        tracked.insert("wafflehacks/cms".to_string(), ids(&["green-1"]));
+        let mut previous = HashMap::new();
+        previous.insert("wafflehacks/cms".to_string(), ids(&["blue-1", "blue-2"]));
+
+        assert_eq!(
+            orphans(containers, &tracked, &previous, &HashSet::new()),
+            vec![("stray".to_string(), "wafflehacks/cms".to_string())]
+        );
+    }
+
+    #[test]
+    fn skips_busy_services() {
+        let containers = [
+            ("creating", "wafflehacks/cms"),
+            ("stray", "wafflehacks/auth"),
+        ]
+        .iter()
+        .map(|(id, service)| (id.to_string(), service.to_string()))
+        .collect();
+
+        let mut busy = HashSet::new();
+        busy.insert("wafflehacks/cms".to_string());
+
+        assert_eq!(
+            orphans(containers, &HashMap::new(), &HashMap::new(), &busy),
+            vec![("stray".to_string(), "wafflehacks/auth".to_string())]
+        );
+    }
+}
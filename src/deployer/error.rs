@@ -1,6 +1,7 @@
 use bollard::errors::Error as BollardError;
+use serde_json::Error as JsonError;
 use sled::Error as SledError;
-use std::{io::Error as IoError, string::FromUtf8Error};
+use std::{io::Error as IoError, path::PathBuf, string::FromUtf8Error};
 use thiserror::Error as ThisError;
 use warp::reject::Reject;
 
@@ -25,6 +26,10 @@ pub enum Error {
     Timeout(#[source] ErrorSource),
     #[error("unable to save state")]
     State(#[source] ErrorSource),
+    #[error("service failed its health check")]
+    Unhealthy,
+    #[error("bind mount source \"{0}\" is not in an allowed directory")]
+    DisallowedBind(PathBuf),
     #[error("an i/o error occurred")]
     Io(#[from] IoError),
     #[error("an unknown error occurred")]
@@ -42,6 +47,8 @@ pub enum ErrorSource {
     Sled(#[from] SledError),
     #[error(transparent)]
     Utf8(#[from] FromUtf8Error),
+    #[error(transparent)]
+    Json(#[from] JsonError),
 }
 
 impl From<BollardError> for Error {
@@ -0,0 +1,33 @@
+use super::{error::Error, instance, Result};
+use crate::service::Health;
+use tokio::time::{self, timeout};
+use tracing::{debug, info, instrument, warn};
+
+/// Wait for a service to pass its health check. Fails once the maximum number of
+/// consecutive failures is reached.
+#[instrument(skip(health), fields(check = health.check.name()))]
+pub async fn wait_until_healthy(id: &str, health: &Health) -> Result<()> {
+    let deployer = instance();
+
+    time::sleep(health.start_period()).await;
+    debug!("start period elapsed");
+
+    let retries = health.retries.max(1);
+    for attempt in 1..=retries {
+        let healthy = match timeout(health.timeout(), deployer.healthy(id, &health.check)).await {
+            Ok(result) => result?,
+            Err(_) => false,
+        };
+        if healthy {
+            info!("service is healthy");
+            return Ok(());
+        }
+
+        warn!(attempt, retries, "health check failed");
+        if attempt != retries {
+            time::sleep(health.interval()).await;
+        }
+    }
+
+    Err(Error::Unhealthy)
+}
@@ -0,0 +1,124 @@
+use crate::service::{persisted, Service};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A record of a single deployment of a service
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    /// The sequential version of the deployment, assigned when it is recorded
+    #[serde(default)]
+    pub version: u64,
+    /// When the deployment happened as a UNIX timestamp
+    pub timestamp: u64,
+    /// The commit the configuration was loaded from
+    pub commit: String,
+    /// The ids of the deployment's replicas
+    pub ids: Vec<String>,
+    pub outcome: Outcome,
+    /// The configuration that was deployed
+    #[serde(deserialize_with = "persisted::deserialize")]
+    pub config: Service,
+}
+
+impl HistoryEntry {
+    /// Create a new history entry for a deployment that just finished
+    pub fn new<S: Into<String>>(
+        commit: S,
+        config: Service,
+        ids: Vec<String>,
+        outcome: Outcome,
+    ) -> HistoryEntry {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        HistoryEntry {
+            version: 0,
+            timestamp,
+            commit: commit.into(),
+            ids,
+            outcome,
+            config,
+        }
+    }
+
+    /// The image and tag that was deployed
+    pub fn image(&self) -> String {
+        format!("{}:{}", self.config.docker.image, self.config.docker.tag)
+    }
+}
+
+/// Whether a deployment replaced the previous one
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+/// Find the entry to roll back to. When no version is given, the most recent successful
+/// deployment before the current one is used.
+pub fn rollback_target(history: &[HistoryEntry], to: Option<u64>) -> Option<&HistoryEntry> {
+    let mut successful = history
+        .iter()
+        .rev()
+        .filter(|e| e.outcome == Outcome::Success);
+
+    match to {
+        Some(version) => successful.find(|e| e.version == version),
+        None => successful.nth(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rollback_target, HistoryEntry, Outcome};
+    use crate::service::Service;
+
+    async fn history(outcomes: &[Outcome]) -> Vec<HistoryEntry> {
+        let config = Service::parse("./example-service.toml").await.unwrap();
+        outcomes
+            .iter()
+            .enumerate()
+            .map(|(i, outcome)| HistoryEntry {
+                version: i as u64 + 1,
+                ..HistoryEntry::new("abcdef", config.clone(), Vec::new(), *outcome)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let entry = history(&[Outcome::Success]).await.remove(0);
+
+        let serialized = serde_json::to_vec(&entry).unwrap();
+        let parsed: HistoryEntry = serde_json::from_slice(&serialized).unwrap();
+
+        assert_eq!(parsed.version, 1);
+        assert_eq!(parsed.outcome, Outcome::Success);
+        assert_eq!(parsed.image(), "wafflehacks/cms:develop");
+        assert_eq!(parsed.config.volumes, entry.config.volumes);
+        assert_eq!(parsed.config.resources, entry.config.resources);
+    }
+
+    #[tokio::test]
+    async fn rollback_to_previous() {
+        let entries = history(&[Outcome::Success, Outcome::Failure, Outcome::Success]).await;
+        assert_eq!(rollback_target(&entries, None).map(|e| e.version), Some(1));
+
+        let entries = history(&[Outcome::Success]).await;
+        assert!(rollback_target(&entries, None).is_none());
+    }
+
+    #[tokio::test]
+    async fn rollback_to_version() {
+        let entries = history(&[Outcome::Success, Outcome::Failure, Outcome::Success]).await;
+        assert_eq!(
+            rollback_target(&entries, Some(3)).map(|e| e.version),
+            Some(3)
+        );
+        assert!(rollback_target(&entries, Some(2)).is_none());
+        assert!(rollback_target(&entries, Some(4)).is_none());
+    }
+}
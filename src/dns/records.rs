@@ -9,9 +9,12 @@ pub(crate) struct Records<'r> {
 }
 
 impl<'r> Records<'r> {
-    pub fn a_record(ip: &'r str) -> Self {
+    pub fn a_records<I>(ips: I) -> Self
+    where
+        I: IntoIterator<Item = &'r str>,
+    {
         Self {
-            a: vec![ARecord { ip, ttl: TTL }],
+            a: ips.into_iter().map(|ip| ARecord { ip, ttl: TTL }).collect(),
         }
     }
 }
@@ -13,15 +13,15 @@ impl Dns {
         Self { client, key }
     }
 
-    /// Register service's DNS record
-    pub async fn register(&self, service: &str, ip: &str) -> RedisResult<()> {
+    /// Register service's DNS record(s), one per replica
+    pub async fn register<S: AsRef<str>>(&self, service: &str, ips: &[S]) -> RedisResult<()> {
         // Build the records
-        let records = Records::a_record(ip);
+        let records = Records::a_records(ips.iter().map(AsRef::as_ref));
         let value = serde_json::to_string(&records).unwrap();
 
         // Insert into the hashmap
         let mut conn = self.client.get_tokio_connection_manager().await?;
-        conn.hset(&self.key, service, value).await?;
+        conn.hset::<_, _, _, ()>(&self.key, service, value).await?;
 
         Ok(())
     }
@@ -29,7 +29,7 @@ impl Dns {
     /// Unregister a service's DNS record
     pub async fn unregister(&self, service: &str) -> RedisResult<()> {
         let mut conn = self.client.get_tokio_connection_manager().await?;
-        conn.hdel(&self.key, service).await?;
+        conn.hdel::<_, _, ()>(&self.key, service).await?;
 
         Ok(())
     }
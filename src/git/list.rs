@@ -0,0 +1,65 @@
+use super::Result;
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// List the paths of all the files in the repository as of a commit
+#[instrument(name = "list", skip(repo))]
+pub(crate) fn run(repo: &Repository, commit: &str) -> Result<Vec<PathBuf>> {
+    let tree = repo.revparse_single(commit)?.peel_to_tree()?;
+
+    let mut paths = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if let (Some(ObjectType::Blob), Some(name)) = (entry.kind(), entry.name()) {
+            paths.push(Path::new(root).join(name));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use git2::{Oid, Repository, Signature};
+    use std::path::PathBuf;
+
+    /// Build a tree containing a file and a nested directory, returning its ID
+    fn tree(repo: &Repository) -> Oid {
+        let blob = repo.blob(b"").unwrap();
+
+        let mut nested = repo.treebuilder(None).unwrap();
+        nested.insert("cms.toml", blob, 0o100644).unwrap();
+        let nested = nested.write().unwrap();
+
+        let mut root = repo.treebuilder(None).unwrap();
+        root.insert("api.toml", blob, 0o100644).unwrap();
+        root.insert("wafflehacks", nested, 0o040000).unwrap();
+        root.write().unwrap()
+    }
+
+    #[test]
+    fn nested_files() {
+        let path = std::env::temp_dir().join(format!("wafflemaker-list-{}", std::process::id()));
+        let repo = Repository::init_bare(&path).unwrap();
+
+        let tree = repo.find_tree(tree(&repo)).unwrap();
+        let signature = Signature::now("wafflemaker", "wafflemaker@localhost").unwrap();
+        let commit = repo
+            .commit(None, &signature, &signature, "initial", &tree, &[])
+            .unwrap();
+
+        let mut paths = run(&repo, &commit.to_string()).unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("api.toml"),
+                PathBuf::from("wafflehacks/cms.toml")
+            ]
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use once_cell::sync::OnceCell;
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::{mpsc, Arc},
     thread::JoinHandle,
 };
@@ -9,7 +9,9 @@ use tracing::instrument;
 
 mod diff;
 mod head;
+mod list;
 mod pull;
+mod read;
 mod service;
 
 pub use diff::{Action, DiffFile};
@@ -54,6 +56,34 @@ impl Repository {
         }
     }
 
+    /// Read the contents of a file as of a commit, if it exists
+    #[instrument(name = "read_dispatch", skip(self))]
+    pub async fn read(&self, commit: String, path: PathBuf) -> Result<Option<Vec<u8>>> {
+        // Send command
+        let (tx, rx) = oneshot::channel();
+        self.0.send((Method::Read(commit, path), tx)).unwrap();
+
+        // Get the result
+        match rx.await.unwrap() {
+            Return::Read(r) => r,
+            _ => unreachable!(),
+        }
+    }
+
+    /// List the paths of all the files as of a commit
+    #[instrument(name = "list_dispatch", skip(self))]
+    pub async fn list(&self, commit: String) -> Result<Vec<PathBuf>> {
+        // Send command
+        let (tx, rx) = oneshot::channel();
+        self.0.send((Method::List(commit), tx)).unwrap();
+
+        // Get the result
+        match rx.await.unwrap() {
+            Return::List(r) => r,
+            _ => unreachable!(),
+        }
+    }
+
     /// Get the current head of the repository
     #[instrument(skip(self))]
     pub async fn head(&self) -> Result<String> {
@@ -0,0 +1,18 @@
+use super::Result;
+use git2::{ErrorCode, Repository};
+use std::path::Path;
+use tracing::instrument;
+
+/// Read the contents of a file as of a commit, returning `None` if it did not exist
+#[instrument(name = "read", skip(repo))]
+pub(crate) fn run(repo: &Repository, commit: &str, path: &Path) -> Result<Option<Vec<u8>>> {
+    let tree = repo.revparse_single(commit)?.peel_to_tree()?;
+    let entry = match tree.get_path(path) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let blob = entry.to_object(repo)?.peel_to_blob()?;
+    Ok(Some(blob.content().to_vec()))
+}
@@ -1,10 +1,10 @@
 use super::{
     diff::{self, DiffFile},
-    head, pull, Result,
+    head, list, pull, read, Result,
 };
 use git2::Repository;
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::mpsc,
     thread::{self, JoinHandle},
 };
@@ -70,6 +70,16 @@ fn handle_call(repo: &Repository, method: Method, tx: oneshot::Sender<Return>) {
             tx.send(Return::Head(result))
                 .expect("failed to send on channel");
         }
+        Method::Read(commit, path) => {
+            let result = read::run(repo, &commit, &path);
+            tx.send(Return::Read(result))
+                .expect("failed to send on channel");
+        }
+        Method::List(commit) => {
+            let result = list::run(repo, &commit);
+            tx.send(Return::List(result))
+                .expect("failed to send on channel");
+        }
         _ => unreachable!(),
     }
 }
@@ -80,6 +90,8 @@ pub enum Method {
     Head,
     Pull(String, String, String),
     Diff(String, String),
+    Read(String, PathBuf),
+    List(String),
     Shutdown,
 }
 
@@ -89,6 +101,8 @@ impl Method {
             Self::Head => "head",
             Self::Pull(_, _, _) => "pull",
             Self::Diff(_, _) => "diff",
+            Self::Read(_, _) => "read",
+            Self::List(_) => "list",
             Self::Shutdown => "shutdown",
         }
     }
@@ -100,4 +114,6 @@ pub enum Return {
     Head(Result<String>),
     Pull(Result<()>),
     Diff(Result<Vec<DiffFile>>),
+    Read(Result<Option<Vec<u8>>>),
+    List(Result<Vec<PathBuf>>),
 }
@@ -1,10 +1,10 @@
-use git2::Error as Git2Error;
+use git2::{Error as Git2Error, ErrorCode};
 use serde::Serialize;
 use std::convert::Infallible;
 use tracing::error;
 use warp::{
     http::StatusCode,
-    reject::{MethodNotAllowed, MissingHeader, PayloadTooLarge, Reject},
+    reject::{InvalidQuery, MethodNotAllowed, MissingHeader, PayloadTooLarge, Reject},
     reply, Rejection, Reply,
 };
 
@@ -30,11 +30,26 @@ impl Reject for UndeployableError {}
 pub struct BodyDeserializeError;
 impl Reject for BodyDeserializeError {}
 
+/// Raised when the commits to deploy are missing or are not full commit hashes
+#[derive(Debug)]
+pub struct InvalidCommitError;
+impl Reject for InvalidCommitError {}
+
 /// Raised when there is an error interacting the the git repo
 #[derive(Debug)]
 pub struct GitError(pub Git2Error);
 impl Reject for GitError {}
 
+/// Raised when the request conflicts with the current state of a resource
+#[derive(Debug)]
+pub struct ConflictError;
+impl Reject for ConflictError {}
+
+/// Raised when there is an error interacting with the state database
+#[derive(Debug)]
+pub struct StorageError(pub sled::Error);
+impl Reject for StorageError {}
+
 /// Convert a `Rejection` to an API error, otherwise simply passes
 /// the rejection along.
 pub async fn recover(error: Rejection) -> Result<impl Reply, Infallible> {
@@ -46,6 +61,8 @@ pub async fn recover(error: Rejection) -> Result<impl Reply, Infallible> {
         message = "not found";
     } else if error.find::<MissingHeader>().is_some()
         || error.find::<BodyDeserializeError>().is_some()
+        || error.find::<InvalidCommitError>().is_some()
+        || error.find::<InvalidQuery>().is_some()
     {
         code = StatusCode::BAD_REQUEST;
         message = "bad request";
@@ -61,6 +78,12 @@ pub async fn recover(error: Rejection) -> Result<impl Reply, Infallible> {
     } else if error.find::<UndeployableError>().is_some() {
         code = StatusCode::FORBIDDEN;
         message = "forbidden";
+    } else if error.find::<ConflictError>().is_some() {
+        code = StatusCode::CONFLICT;
+        message = "conflict";
+    } else if matches!(error.find::<GitError>(), Some(e) if e.0.code() == ErrorCode::NotFound) {
+        code = StatusCode::NOT_FOUND;
+        message = "not found";
     } else if let Some(e) = error.find::<GitError>() {
         error!(
             "error while interacting with local repo: ({:?}, {:?}) {}",
@@ -70,6 +93,10 @@ pub async fn recover(error: Rejection) -> Result<impl Reply, Infallible> {
         );
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "internal server error";
+    } else if let Some(e) = error.find::<StorageError>() {
+        error!("error while interacting with state database: {}", e.0);
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "internal server error";
     } else {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "internal server error";
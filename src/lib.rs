@@ -0,0 +1,4 @@
+//! The service configuration format shared between WaffleMaker and wafflectl
+
+pub mod config;
+pub mod service;
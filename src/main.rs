@@ -11,7 +11,7 @@ use tokio::{
     sync::broadcast,
     task,
 };
-use tracing::{info, Level};
+use tracing::{error, info, Level};
 use tracing_subscriber::{
     fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
 };
@@ -20,7 +20,6 @@ use warp::Filter;
 use args::Args;
 
 mod args;
-mod config;
 mod deployer;
 mod dns;
 mod git;
@@ -28,12 +27,13 @@ mod http;
 mod management;
 mod notifier;
 mod processor;
-mod service;
 mod vault;
 mod webhooks;
 
-use config::Config;
-use service::registry;
+use wafflemaker::{
+    config::{self, Config},
+    service::{self, registry},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -76,6 +76,9 @@ async fn main() -> Result<()> {
 async fn run_server(address: SocketAddr, configuration: &Config) -> Result<()> {
     let (stop_tx, mut stop_rx) = broadcast::channel(1);
 
+    // Credentials are still needed while the running jobs finish, so Vault is stopped separately
+    let (vault_stop_tx, _) = broadcast::channel(1);
+
     // Initialize the service registry
     registry::init().await?;
 
@@ -86,12 +89,15 @@ async fn run_server(address: SocketAddr, configuration: &Config) -> Result<()> {
     deployer::initialize(
         &configuration.deployment,
         &configuration.dns.server,
-        stop_tx.subscribe(),
+        stop_tx.clone(),
     )
     .await?;
 
+    // Restore the job queue from the deployer's state, nothing can dispatch jobs before this
+    processor::jobs::initialize()?;
+
     // Connect to Vault (secrets service)
-    vault::initialize(&configuration.secrets, stop_tx.clone()).await?;
+    vault::initialize(&configuration.secrets, vault_stop_tx.clone()).await?;
 
     // Connect to the DNS management service
     dns::initialize(&configuration.dns).await?;
@@ -100,7 +106,7 @@ async fn run_server(address: SocketAddr, configuration: &Config) -> Result<()> {
     notifier::initialize()?;
 
     // Start the job processor
-    processor::spawn(stop_tx.clone());
+    let processor = processor::spawn(stop_tx.clone());
 
     // Start the management interface
     management::start(stop_tx.clone())?;
@@ -125,9 +131,22 @@ async fn run_server(address: SocketAddr, configuration: &Config) -> Result<()> {
         .context("failed to listen for event")?;
     info!("signal received, shutting down...");
 
-    // Shutdown the services
+    // Shutdown the services, no new jobs will be started
     stop_tx.send(()).unwrap();
 
+    // Let the running jobs finish so nothing is left halfway through a deployment
+    processor
+        .shutdown(configuration.agent.shutdown_timeout())
+        .await;
+
+    // Keep the credentials alive until the jobs using them are done
+    vault_stop_tx.send(()).ok();
+
+    // The state database is never dropped, so anything still buffered must be saved now
+    if let Err(e) = deployer::instance().flush().await {
+        error!(error = %e, "failed to flush state database");
+    }
+
     // Shutdown the repository service
     git::instance().shutdown();
     repository_handle.join().unwrap();
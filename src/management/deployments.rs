@@ -1,12 +1,15 @@
 use crate::{
     config, deployer, git,
-    http::{named_trace, GitError},
-    processor::jobs::{self, PlanUpdate},
+    http::{named_trace, GitError, InvalidCommitError},
+    processor::{
+        jobs::{self, PlanUpdate},
+        plan,
+    },
     service::registry::REGISTRY,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use warp::http::StatusCode;
-use warp::{Filter, Rejection, Reply};
+use warp::{reject, Filter, Rejection, Reply};
 
 /// Build the routes for deployments
 pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
@@ -21,7 +24,19 @@ pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clo
         .and_then(rerun)
         .with(named_trace("rerun"));
 
-    warp::path("deployments").and(get.or(post))
+    // Requests to plan without both commits are rejected here rather than being left to fall
+    // through to the rerun route
+    let plan = warp::post()
+        .and(warp::path("plan"))
+        .and(warp::path::end())
+        .and(
+            warp::query::<PlanQuery>()
+                .or_else(|_| async { Err(reject::custom(InvalidCommitError)) }),
+        )
+        .and_then(plan)
+        .with(named_trace("plan"));
+
+    warp::path("deployments").and(get.or(plan).or(post))
 }
 
 #[derive(Debug, Serialize)]
@@ -50,6 +65,10 @@ async fn get() -> Result<impl Reply, Rejection> {
 
 /// Re-run a deployment given the commit hash of the before state
 async fn rerun(before: String) -> Result<impl Reply, Rejection> {
+    if !is_commit(&before) {
+        return Err(reject::custom(InvalidCommitError));
+    }
+
     let current = git::instance().head().await.map_err(GitError)?;
 
     let path = &config::instance().git.clone_to;
@@ -57,3 +76,57 @@ async fn rerun(before: String) -> Result<impl Reply, Rejection> {
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[derive(Debug, Deserialize)]
+struct PlanQuery {
+    before: String,
+    after: String,
+}
+
+/// Preview what would change when deploying between two commits
+async fn plan(query: PlanQuery) -> Result<impl Reply, Rejection> {
+    let plan = plan::preview(&query.before, &query.after)
+        .await
+        .map_err(GitError)?;
+
+    Ok(warp::reply::json(&plan))
+}
+
+/// Whether the value is a full commit hash
+fn is_commit(value: &str) -> bool {
+    value.len() == 40 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::http::recover;
+    use warp::{http::StatusCode, test::request, Filter};
+
+    #[tokio::test]
+    async fn reject_invalid_commits() {
+        let routes = routes().recover(recover);
+
+        let missing = request()
+            .method("POST")
+            .path("/deployments/plan")
+            .reply(&routes)
+            .await;
+        assert_eq!(missing.status(), StatusCode::BAD_REQUEST);
+
+        let partial = request()
+            .method("POST")
+            .path("/deployments/plan?before=786ef0fae1096bd1fc01c0c6fc096c9bec37835b")
+            .reply(&routes)
+            .await;
+        assert_eq!(partial.status(), StatusCode::BAD_REQUEST);
+
+        // The plan route is never mistaken for a commit to rerun from
+        let rerun = request()
+            .method("PUT")
+            .path("/deployments/plan")
+            .reply(&routes)
+            .await;
+        assert_eq!(rerun.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::deployer::{self, Exec, ExecOutput};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, select};
+use tracing::{debug, error, info, instrument};
+use warp::ws::{Message, WebSocket};
+
+/// Prefixes for binary messages indicating which stream the output came from
+const STDOUT: u8 = 1;
+const STDERR: u8 = 2;
+
+/// Control messages sent by the client as text. Any binary messages are forwarded to the
+/// command's stdin.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Request {
+    /// Start the command, must be the first message
+    Start {
+        command: Vec<String>,
+        #[serde(default)]
+        tty: bool,
+        width: Option<u16>,
+        height: Option<u16>,
+    },
+    /// Change the size of the TTY
+    Resize { width: u16, height: u16 },
+    /// Close the command's stdin
+    Eof,
+}
+
+/// Control messages sent to the client as text. Any output is sent as binary messages prefixed
+/// with the stream it came from.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Response {
+    /// The command finished
+    Exit { code: Option<i64> },
+    /// The command could not be run
+    Error { message: String },
+}
+
+impl From<Response> for Message {
+    fn from(response: Response) -> Message {
+        Message::text(serde_json::to_string(&response).unwrap())
+    }
+}
+
+/// Run a command in a container, bridging its input and output over the WebSocket
+#[instrument(skip(socket))]
+pub async fn bridge(socket: WebSocket, id: String) {
+    let (mut tx, mut rx) = socket.split();
+
+    // Wait for the command to run
+    let (command, tty, size) = match rx.next().await.map(|m| m.map(parse)) {
+        Some(Ok(Some(Request::Start {
+            command,
+            tty,
+            width,
+            height,
+        }))) if !command.is_empty() => (command, tty, width.zip(height)),
+        _ => {
+            let message = "expected a start message with a command".to_string();
+            tx.send(Response::Error { message }.into()).await.ok();
+            tx.close().await.ok();
+            return;
+        }
+    };
+
+    let exec = match deployer::instance().exec(&id, &command, tty).await {
+        Ok(exec) => exec,
+        Err(e) => {
+            error!(error = %e, "failed to start command");
+            let message = e.to_string();
+            tx.send(Response::Error { message }.into()).await.ok();
+            tx.close().await.ok();
+            return;
+        }
+    };
+    info!(exec = %exec.id, ?command, tty, "started command");
+
+    let Exec {
+        id: exec_id,
+        mut input,
+        mut output,
+    } = exec;
+    if let Some((width, height)) = size {
+        if let Err(e) = deployer::instance()
+            .resize_exec(&exec_id, width, height)
+            .await
+        {
+            debug!(error = %e, "failed to set initial tty size");
+        }
+    }
+
+    let forward_output = async {
+        while let Some(chunk) = output.next().await {
+            let (stream, bytes) = match chunk {
+                Ok(ExecOutput::Stdout(bytes)) => (STDOUT, bytes),
+                Ok(ExecOutput::Stderr(bytes)) => (STDERR, bytes),
+                Err(e) => {
+                    error!(error = %e, "failed to read command output");
+                    break;
+                }
+            };
+
+            let mut message = Vec::with_capacity(bytes.len() + 1);
+            message.push(stream);
+            message.extend_from_slice(&bytes);
+            if tx.send(Message::binary(message)).await.is_err() {
+                break;
+            }
+        }
+    };
+    let forward_input = async {
+        while let Some(Ok(message)) = rx.next().await {
+            if message.is_binary() {
+                if input.write_all(message.as_bytes()).await.is_err() {
+                    break;
+                }
+                input.flush().await.ok();
+                continue;
+            }
+
+            match parse(message) {
+                Some(Request::Resize { width, height }) => {
+                    if let Err(e) = deployer::instance()
+                        .resize_exec(&exec_id, width, height)
+                        .await
+                    {
+                        debug!(error = %e, "failed to resize tty");
+                    }
+                }
+                Some(Request::Eof) => {
+                    input.shutdown().await.ok();
+                }
+                Some(Request::Start { .. }) => debug!("command already started"),
+                None => {}
+            }
+        }
+    };
+
+    // The output ends when the command exits, the input ends when the client disconnects
+    select! {
+        _ = forward_output => {}
+        _ = forward_input => {
+            info!("client disconnected");
+            return;
+        }
+    }
+
+    let code = match deployer::instance().exec_exit_code(&exec_id).await {
+        Ok(code) => code,
+        Err(e) => {
+            error!(error = %e, "failed to get command exit code");
+            None
+        }
+    };
+    info!(?code, "command exited");
+
+    tx.send(Response::Exit { code }.into()).await.ok();
+    tx.close().await.ok();
+}
+
+/// Parse a control message from the client, ignoring anything that isn't valid
+fn parse(message: Message) -> Option<Request> {
+    let text = message.to_str().ok()?;
+    serde_json::from_str(text).ok()
+}
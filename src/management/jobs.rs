@@ -0,0 +1,68 @@
+use crate::{
+    http::{named_trace, ConflictError, StorageError},
+    processor::jobs::{self, CancelError, Status},
+};
+use serde::Deserialize;
+use warp::{Filter, Rejection, Reply};
+
+/// Build the routes for jobs
+pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let list = warp::get()
+        .and(warp::path::end())
+        .and(warp::query())
+        .and_then(list)
+        .with(named_trace("list"));
+
+    let get = warp::get()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and_then(get)
+        .with(named_trace("get"));
+
+    let cancel = warp::delete()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and_then(cancel)
+        .with(named_trace("cancel"));
+
+    warp::path("jobs").and(list.or(get).or(cancel))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    status: Option<Status>,
+    parent: Option<u64>,
+    service: Option<String>,
+}
+
+/// Get all the jobs that haven't been pruned, oldest first
+async fn list(query: ListQuery) -> Result<impl Reply, Rejection> {
+    let mut records = jobs::instance().records().map_err(StorageError)?;
+    records.retain(|record| {
+        query.status.is_none_or(|s| record.status == s)
+            && query.parent.is_none_or(|p| record.parent == Some(p))
+            && query
+                .service
+                .as_ref()
+                .is_none_or(|s| record.service.as_ref() == Some(s))
+    });
+
+    Ok(warp::reply::json(&records))
+}
+
+/// Get the details of a job
+async fn get(id: u64) -> Result<impl Reply, Rejection> {
+    match jobs::instance().record(id) {
+        Some(record) => Ok(warp::reply::json(&record)),
+        None => Err(warp::reject::not_found()),
+    }
+}
+
+/// Cancel a job that hasn't started running yet
+async fn cancel(id: u64) -> Result<impl Reply, Rejection> {
+    match jobs::instance().cancel(id) {
+        Ok(record) => Ok(warp::reply::json(&record)),
+        Err(CancelError::NotFound) => Err(warp::reject::not_found()),
+        Err(CancelError::NotPending(_)) => Err(ConflictError.into()),
+    }
+}
@@ -61,7 +61,7 @@ impl HttpLease {
 #[derive(Debug, Serialize)]
 struct Response<'l> {
     leases: HashMap<&'l str, Vec<HttpLease>>,
-    services: HashMap<String, String>,
+    services: HashMap<String, Vec<String>>,
 }
 
 /// Get all the currently registered leases
@@ -88,7 +88,13 @@ async fn list() -> Result<impl Reply, Rejection> {
 async fn add(service: String, body: HttpLease) -> Result<impl Reply, Rejection> {
     let mut leases = LEASES.write().await;
 
-    if let Some(id) = deployer::instance().service_id(&service).await? {
+    // Leases are shared between replicas and tracked with the first one
+    if let Some(id) = deployer::instance()
+        .service_ids(&service)
+        .await?
+        .into_iter()
+        .next()
+    {
         leases.entry(id).or_default().push(body.into_lease());
     }
 
@@ -104,7 +110,13 @@ struct Delete {
 async fn delete(service: String, params: Delete) -> Result<impl Reply, Rejection> {
     let mut leases = LEASES.write().await;
 
-    if let Some(id) = deployer::instance().service_id(&service).await? {
+    // Leases are shared between replicas and tracked with the first one
+    if let Some(id) = deployer::instance()
+        .service_ids(&service)
+        .await?
+        .into_iter()
+        .next()
+    {
         let mut empty = false;
 
         // Remove any entries matching the id
@@ -8,8 +8,11 @@ use tracing::{info, instrument};
 use warp::{Error, Filter, Rejection};
 
 mod deployments;
+mod exec;
+mod jobs;
 mod leases;
 mod services;
+mod validate;
 
 /// Start the management interface
 #[instrument(skip(stop_tx))]
@@ -23,8 +26,10 @@ pub fn start(stop_tx: Sender<()>) -> Result<(), Error> {
 
     // Build the routes
     let routes = deployments::routes()
+        .or(jobs::routes())
         .or(leases::routes())
-        .or(services::routes());
+        .or(services::routes())
+        .or(validate::routes());
     let with_middleware = warp::any()
         .and(authentication(&config.token).and(routes))
         .recover(recover);
@@ -1,11 +1,21 @@
 use crate::{
-    config, deployer,
+    config,
+    deployer::{self, HistoryEntry, LogOpts, Outcome},
     http::named_trace,
     processor::jobs::{self, DeleteService, UpdateService},
     registry::REGISTRY,
+    service::Resources,
+};
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use warp::{
+    http::{header::CONTENT_TYPE, Response as HttpResponse, StatusCode},
+    hyper::Body,
+    path::Tail,
+    ws::Ws,
+    Filter, Rejection, Reply,
 };
-use serde::Serialize;
-use warp::{http::StatusCode, path::Tail, Filter, Rejection, Reply};
 
 /// Build the routes for services
 pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
@@ -14,6 +24,25 @@ pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clo
         .and_then(list)
         .with(named_trace("list"));
 
+    let history = warp::get()
+        .and(service_action("history"))
+        .and_then(history)
+        .with(named_trace("history"));
+    let rollback = warp::post()
+        .and(service_action("rollback"))
+        .and(warp::query())
+        .and_then(rollback)
+        .with(named_trace("rollback"));
+    let logs = warp::get()
+        .and(service_action("logs"))
+        .and(warp::query())
+        .and_then(logs)
+        .with(named_trace("logs"));
+    let exec = service_action("exec")
+        .and(warp::ws())
+        .and_then(exec)
+        .with(named_trace("exec"));
+
     let get = warp::get()
         .and(warp::path::tail())
         .and_then(get)
@@ -27,7 +56,30 @@ pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clo
         .and_then(delete)
         .with(named_trace("delete"));
 
-    warp::path("services").and(list.or(get).or(redeploy).or(delete))
+    warp::path("services").and(
+        list.or(history)
+            .or(rollback)
+            .or(logs)
+            .or(exec)
+            .or(get)
+            .or(redeploy)
+            .or(delete),
+    )
+}
+
+/// Extract the service name from a path in the form of `{name}/-/{action}`. The separator keeps
+/// actions apart from services whose names end in the same segment.
+fn service_action(
+    action: &'static str,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::path::tail().and_then(move |tail: Tail| async move {
+        tail.as_str()
+            .strip_suffix(action)
+            .and_then(|s| s.strip_suffix("/-/"))
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .ok_or_else(warp::reject::not_found)
+    })
 }
 
 /// Get a list of all the currently deployed services
@@ -44,7 +96,9 @@ struct Response {
     image: String,
     automatic_updates: bool,
     domain: Option<String>,
-    deployment_id: Option<String>,
+    replicas: u32,
+    resources: ResourcesResponse,
+    deployment_ids: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -53,6 +107,29 @@ struct DependenciesResponse {
     redis: bool,
 }
 
+#[derive(Debug, Serialize)]
+struct ResourcesResponse {
+    memory: Option<u64>,
+    memory_reservation: Option<u64>,
+    cpu_shares: Option<i64>,
+    cpu_period: Option<i64>,
+    cpu_quota: Option<i64>,
+    pids: Option<i64>,
+}
+
+impl From<&Resources> for ResourcesResponse {
+    fn from(resources: &Resources) -> ResourcesResponse {
+        ResourcesResponse {
+            memory: resources.memory.map(|m| m.0),
+            memory_reservation: resources.memory_reservation.map(|m| m.0),
+            cpu_shares: resources.cpu_shares,
+            cpu_period: resources.cpu_period,
+            cpu_quota: resources.cpu_quota,
+            pids: resources.pids,
+        }
+    }
+}
+
 /// Get the configuration for a service
 async fn get(service: Tail) -> Result<impl Reply, Rejection> {
     let service = service.as_str();
@@ -60,7 +137,13 @@ async fn get(service: Tail) -> Result<impl Reply, Rejection> {
     let reg = REGISTRY.read().await;
     let cfg = reg.get(service).ok_or_else(warp::reject::not_found)?;
 
-    let deployment_id = deployer::instance().service_id(service).await?;
+    let deployment_ids = deployer::instance().service_ids(service).await?;
+
+    // All the replicas are created with the same limits, so any of them reflect what is applied
+    let resources = match deployment_ids.first() {
+        Some(id) => deployer::instance().resources(id).await?,
+        None => Resources::default(),
+    };
 
     let dependencies = DependenciesResponse {
         postgres: cfg.dependencies.postgres("").is_some(),
@@ -86,7 +169,9 @@ async fn get(service: Tail) -> Result<impl Reply, Rejection> {
         image: format!("{}:{}", cfg.docker.image, cfg.docker.tag),
         automatic_updates: cfg.docker.update.automatic,
         domain,
-        deployment_id,
+        replicas: cfg.replicas,
+        resources: ResourcesResponse::from(&resources),
+        deployment_ids,
     }))
 }
 
@@ -102,8 +187,202 @@ async fn redeploy(service: Tail) -> Result<impl Reply, Rejection> {
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Debug, Serialize)]
+struct HistoryResponse {
+    version: u64,
+    timestamp: u64,
+    image: String,
+    commit: String,
+    deployment_ids: Vec<String>,
+    outcome: Outcome,
+}
+
+impl From<&HistoryEntry> for HistoryResponse {
+    fn from(entry: &HistoryEntry) -> HistoryResponse {
+        HistoryResponse {
+            version: entry.version,
+            timestamp: entry.timestamp,
+            image: entry.image(),
+            commit: entry.commit.clone(),
+            deployment_ids: entry.ids.clone(),
+            outcome: entry.outcome,
+        }
+    }
+}
+
+/// Get the deployment history of a service, most recent first
+async fn history(service: String) -> Result<impl Reply, Rejection> {
+    let history = deployer::instance().history(&service).await?;
+    if history.is_empty() {
+        return Err(warp::reject::not_found());
+    }
+
+    let response = history
+        .iter()
+        .rev()
+        .map(HistoryResponse::from)
+        .collect::<Vec<_>>();
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Deserialize)]
+struct RollbackQuery {
+    to: Option<u64>,
+}
+
+/// Re-deploy a previous version of a service from its history
+async fn rollback(service: String, query: RollbackQuery) -> Result<impl Reply, Rejection> {
+    let history = deployer::instance().history(&service).await?;
+    let entry =
+        deployer::rollback_target(&history, query.to).ok_or_else(warp::reject::not_found)?;
+
+    jobs::dispatch(UpdateService::with_commit(
+        entry.config.clone(),
+        service.as_str().into(),
+        &entry.commit,
+    ));
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsQuery {
+    #[serde(default)]
+    follow: bool,
+    tail: Option<u64>,
+    since: Option<i64>,
+}
+
+/// Stream the logs from all of a service's replicas
+async fn logs(service: String, query: LogsQuery) -> Result<impl Reply, Rejection> {
+    let ids = deployer::instance().service_ids(&service).await?;
+    if ids.is_empty() {
+        return Err(warp::reject::not_found());
+    }
+
+    let options = LogOpts {
+        follow: query.follow,
+        tail: query.tail,
+        since: query.since,
+    };
+
+    // Lines from multiple replicas are interleaved, so they get labelled with where they came from
+    let mut streams = Vec::new();
+    for id in &ids {
+        let logs = deployer::instance().logs(id, options).await?;
+        match ids.len() {
+            1 => streams.push(logs),
+            _ => streams.push(prefix_lines(id, logs).boxed()),
+        }
+    }
+
+    let response = HttpResponse::builder()
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::wrap_stream(stream::select_all(streams)))
+        .unwrap();
+    Ok(response)
+}
+
+/// Split a replica's output into whole lines, prefixing each with the replica's short ID
+fn prefix_lines(
+    id: &str,
+    logs: BoxStream<'static, Result<Bytes, deployer::Error>>,
+) -> impl Stream<Item = Result<Bytes, deployer::Error>> {
+    let prefix = format!("[{}] ", &id[..id.len().min(12)]);
+
+    stream::unfold(
+        (logs, Vec::new(), false),
+        move |(mut logs, mut buffer, mut finished)| {
+            let prefix = prefix.clone();
+            async move {
+                loop {
+                    let end = match buffer.iter().position(|b| *b == b'\n') {
+                        Some(index) => index + 1,
+                        None if finished && !buffer.is_empty() => {
+                            buffer.push(b'\n');
+                            buffer.len()
+                        }
+                        None if finished => return None,
+                        None => {
+                            match logs.next().await {
+                                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                                Some(Err(e)) => return Some((Err(e), (logs, buffer, finished))),
+                                None => finished = true,
+                            }
+                            continue;
+                        }
+                    };
+
+                    let mut line = prefix.clone().into_bytes();
+                    line.extend(buffer.drain(..end));
+                    return Some((Ok(Bytes::from(line)), (logs, buffer, finished)));
+                }
+            }
+        },
+    )
+}
+
+/// Run a command in a service's first replica, bridging its input and output over a WebSocket
+async fn exec(service: String, ws: Ws) -> Result<impl Reply, Rejection> {
+    let ids = deployer::instance().service_ids(&service).await?;
+    let id = ids.into_iter().next().ok_or_else(warp::reject::not_found)?;
+
+    Ok(ws.on_upgrade(move |socket| super::exec::bridge(socket, id)))
+}
+
 /// Delete a service
 async fn delete(service: Tail) -> Result<impl Reply, Rejection> {
     jobs::dispatch(DeleteService::new(service.as_str().into()));
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{prefix_lines, service_action};
+    use bytes::Bytes;
+    use futures::stream::{self, StreamExt, TryStreamExt};
+
+    #[tokio::test]
+    async fn action_paths() {
+        let history = service_action("history");
+
+        let name = warp::test::request()
+            .path("/wafflehacks/cms/-/history")
+            .filter(&history)
+            .await
+            .unwrap();
+        assert_eq!(name, "wafflehacks/cms");
+
+        // Services can be named after actions without being mistaken for them
+        assert!(
+            !warp::test::request()
+                .path("/wafflehacks/history")
+                .matches(&history)
+                .await
+        );
+        assert!(
+            !warp::test::request()
+                .path("/-/history")
+                .matches(&history)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn prefixed_lines() {
+        let chunks = ["first\nsec", "ond\n", "last"].map(|chunk| Ok(Bytes::from(chunk)));
+        let lines = prefix_lines("a9b76f566826914892fb", stream::iter(chunks).boxed())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "[a9b76f566826] first\n",
+                "[a9b76f566826] second\n",
+                "[a9b76f566826] last\n"
+            ]
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{
+    config,
+    http::named_trace,
+    service::{self, profile},
+};
+use anyhow::anyhow;
+use bytes::Bytes;
+use serde::Serialize;
+use warp::{Filter, Rejection, Reply};
+
+pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path("validate"))
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(warp::body::bytes())
+        .and_then(validate)
+        .with(named_trace("validate"))
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    valid: bool,
+    issues: Vec<service::Issue>,
+}
+
+/// Check a service configuration without deploying it, using the profiles from the
+/// currently deployed commit
+async fn validate(raw: Bytes) -> Result<impl Reply, Rejection> {
+    let profiles = profile::load_all(&config::instance().git.clone_to).await;
+    let issues = service::validate(&raw, |name| match &profiles {
+        Ok(profiles) => profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("unknown profile {:?}", name)),
+        Err(e) => Err(anyhow!("failed to load profiles: {:#}", e)),
+    });
+
+    Ok(warp::reply::json(&Response {
+        valid: issues.is_empty(),
+        issues,
+    }))
+}
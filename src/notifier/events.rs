@@ -3,9 +3,39 @@ use std::fmt;
 /// Possible events that can be emitted
 #[derive(Debug)]
 pub enum Event<'commit, 'name> {
-    Deployment { commit: &'commit str, state: State },
-    ServiceUpdate { name: &'name str, state: State },
-    ServiceDelete { name: &'name str, state: State },
+    Deployment {
+        commit: &'commit str,
+        state: State,
+    },
+    ServiceUpdate {
+        name: &'name str,
+        state: State,
+    },
+    ServiceDelete {
+        name: &'name str,
+        state: State,
+    },
+    CrashLoop {
+        name: &'name str,
+        state: State,
+    },
+    JobRun {
+        name: &'name str,
+        code: i64,
+        logs: &'name str,
+        state: State,
+    },
+    ScheduledRun {
+        name: &'name str,
+        code: Option<i64>,
+        logs: &'name str,
+        state: State,
+    },
+    Reconciliation {
+        redeployed: &'name [String],
+        removed: &'name [String],
+        state: State,
+    },
 }
 
 impl<'commit, 'name> Event<'commit, 'name> {
@@ -42,11 +72,90 @@ impl<'commit, 'name> Event<'commit, 'name> {
         }
     }
 
+    /// Create a new crash loop event
+    pub fn crash_loop<S>(name: &'name S, restarts: u32) -> Self
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let message = format!(
+            "exited unexpectedly after {} restarts, it will stay stopped until the next deploy",
+            restarts
+        );
+        Self::CrashLoop {
+            name: name.as_ref(),
+            state: State::Failure(message),
+        }
+    }
+
+    /// Create a new event for a job that ran to completion
+    pub fn job_run<S>(name: &'name S, code: i64, logs: &'name str) -> Self
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let state = match code {
+            0 => State::Success,
+            _ => State::Failure(format!("exited with status code {}", code)),
+        };
+        Self::JobRun {
+            name: name.as_ref(),
+            code,
+            logs,
+            state,
+        }
+    }
+
+    /// Create a new event for a scheduled run that could not be completed
+    pub fn scheduled_run<S>(name: &'name S, state: State) -> Self
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        Self::ScheduledRun {
+            name: name.as_ref(),
+            code: None,
+            logs: "",
+            state,
+        }
+    }
+
+    /// Create a new event for a scheduled run that exited
+    pub fn scheduled_run_exited<S>(name: &'name S, code: i64, logs: &'name str) -> Self
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let state = match code {
+            0 => State::Success,
+            _ => State::Failure(format!("exited with status code {}", code)),
+        };
+        Self::ScheduledRun {
+            name: name.as_ref(),
+            code: Some(code),
+            logs,
+            state,
+        }
+    }
+
+    /// Create a new reconciliation event
+    pub fn reconciliation(
+        redeployed: &'name [String],
+        removed: &'name [String],
+        state: State,
+    ) -> Self {
+        Self::Reconciliation {
+            redeployed,
+            removed,
+            state,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Event::Deployment { .. } => "deployment",
             Event::ServiceUpdate { .. } => "service update",
             Event::ServiceDelete { .. } => "service delete",
+            Event::CrashLoop { .. } => "crash loop",
+            Event::JobRun { .. } => "job run",
+            Event::ScheduledRun { .. } => "scheduled run",
+            Event::Reconciliation { .. } => "reconciliation",
         }
     }
 }
@@ -28,7 +28,7 @@ struct Embed<'key, 'value> {
     fields: Vec<Field<'key, 'value>>,
 }
 
-impl<'f, 'n, 'v> From<&Event<'v, 'v>> for Embed<'n, 'v> {
+impl<'n, 'v> From<&Event<'v, 'v>> for Embed<'n, 'v> {
     fn from(event: &Event<'v, 'v>) -> Embed<'n, 'v> {
         let mut fields = Vec::new();
 
@@ -45,10 +45,53 @@ impl<'f, 'n, 'v> From<&Event<'v, 'v>> for Embed<'n, 'v> {
                 fields.push(Field::new("Version", commit_url, true));
                 state
             }
-            Event::ServiceUpdate { name, state } | Event::ServiceDelete { name, state } => {
+            Event::ServiceUpdate { name, state }
+            | Event::ServiceDelete { name, state }
+            | Event::CrashLoop { name, state } => {
                 fields.push(Field::new("Service", *name, true));
                 state
             }
+            Event::JobRun {
+                name,
+                code,
+                logs,
+                state,
+            } => {
+                fields.push(Field::new("Service", *name, true));
+                fields.push(Field::new("Exit code", code.to_string(), true));
+                if !logs.is_empty() {
+                    fields.push(Field::new("Logs", code_block(logs), false));
+                }
+                state
+            }
+            Event::ScheduledRun {
+                name,
+                code,
+                logs,
+                state,
+            } => {
+                fields.push(Field::new("Service", *name, true));
+                if let Some(code) = code {
+                    fields.push(Field::new("Exit code", code.to_string(), true));
+                }
+                if !logs.is_empty() {
+                    fields.push(Field::new("Logs", code_block(logs), false));
+                }
+                state
+            }
+            Event::Reconciliation {
+                redeployed,
+                removed,
+                state,
+            } => {
+                if !redeployed.is_empty() {
+                    fields.push(Field::new("Redeployed", redeployed.join("\n"), false));
+                }
+                if !removed.is_empty() {
+                    fields.push(Field::new("Removed", removed.join("\n"), false));
+                }
+                state
+            }
         };
 
         // Add state information
@@ -109,6 +152,20 @@ impl From<&State> for Color {
     }
 }
 
+/// Format the end of some text as a code block, fitting within the 1024 character limit
+/// of a field's value
+fn code_block(text: &str) -> String {
+    const LIMIT: usize = 1024 - 8;
+
+    let text = text.trim_end();
+    let mut start = text.len().saturating_sub(LIMIT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+
+    format!("```\n{}\n```", text[start..].replace("```", "'''"))
+}
+
 /// Convert the first character of a string to uppercase
 fn uppercase(s: &str) -> String {
     let mut c = s.chars();
@@ -33,7 +33,12 @@ pub async fn dispatch(
         }
 
         // Ignore any service events
-        Event::ServiceUpdate { .. } | Event::ServiceDelete { .. } => {
+        Event::ServiceUpdate { .. }
+        | Event::ServiceDelete { .. }
+        | Event::CrashLoop { .. }
+        | Event::JobRun { .. }
+        | Event::ScheduledRun { .. }
+        | Event::Reconciliation { .. } => {
             debug!("unsupported event, no message sent");
             Ok(())
         }
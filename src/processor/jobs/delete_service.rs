@@ -1,14 +1,15 @@
-use super::Job;
+use super::{Error, Job};
 use crate::{
     deployer, dns, fail_notify,
     notifier::{self, Event, State},
-    service::{registry::REGISTRY, ServiceName},
+    service::{registry::REGISTRY, ServiceName, Volume},
     vault,
 };
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info, instrument};
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DeleteService {
     name: ServiceName,
 }
@@ -23,37 +24,60 @@ impl DeleteService {
 #[async_trait]
 impl Job for DeleteService {
     #[instrument(skip(self), fields(name = %self.name))]
-    async fn run(&self) {
+    async fn run(&self) -> Result<(), Error> {
         macro_rules! fail {
             ($result:expr) => {
                 fail_notify!(service_delete, &self.name; $result; "an error occurred while deleting service")
             };
         }
 
-        let mut reg = REGISTRY.write().await;
-        if reg.remove(&self.name.proper).is_none() {
-            info!("service was never deployed, skipping");
-            notifier::notify(Event::service_delete(&self.name, State::Success)).await;
-            return;
-        }
-
-        notifier::notify(Event::service_delete(&self.name, State::InProgress)).await;
-
-        let id = match fail!(deployer::instance().service_id(&self.name).await) {
-            Some(id) => id,
+        // The queue ensures only this job is modifying the service so the registry doesn't
+        // need to stay locked
+        let removed = REGISTRY.write().await.remove(&self.name.proper);
+        let service = match removed {
+            Some(service) => service,
             None => {
-                info!("deployment does not exist");
-                return;
+                info!("service was never deployed, skipping");
+                notifier::notify(Event::service_delete(&self.name, State::Success)).await;
+                return Ok(());
             }
         };
 
-        if deployer::instance().stop(&id).await.is_err() {
-            debug!("deployment already stopped");
+        notifier::notify(Event::service_delete(&self.name, State::InProgress)).await;
+
+        let ids = fail!(deployer::instance().service_ids(&self.name).await);
+        if ids.is_empty() {
+            info!("deployment does not exist");
+            return Ok(());
+        }
+
+        for id in &ids {
+            if deployer::instance().stop(id).await.is_err() {
+                debug!(%id, "deployment already stopped");
+            }
         }
 
         fail!(deployer::instance().delete_by_name(&self.name).await);
 
-        fail!(vault::instance().revoke_leases(&id).await);
+        for volume in &service.volumes {
+            if let Volume::Named {
+                name,
+                retain: false,
+                ..
+            } = volume
+            {
+                fail!(
+                    deployer::instance()
+                        .delete_volume(&self.name.proper, name)
+                        .await
+                );
+                debug!(%name, "deleted volume");
+            }
+        }
+
+        for id in &ids {
+            fail!(vault::instance().revoke_leases(id).await);
+        }
 
         fail!(dns::instance().unregister(&self.name.domain).await);
 
@@ -67,9 +91,14 @@ impl Job for DeleteService {
 
         info!("successfully deleted deployment");
         notifier::notify(Event::service_delete(&self.name, State::Success)).await;
+        Ok(())
     }
 
     fn name<'a>(&self) -> &'a str {
         "delete_service"
     }
+
+    fn service(&self) -> Option<&ServiceName> {
+        Some(&self.name)
+    }
 }
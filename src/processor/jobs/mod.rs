@@ -1,23 +1,90 @@
+use crate::{config, deployer, service::ServiceName, vault};
 use async_trait::async_trait;
-use deadqueue::unlimited::Queue;
 use once_cell::sync::Lazy;
-use std::sync::Arc;
+use serde::Serialize;
+use serde_json::Value;
+use std::{any::Any, future::Future, sync::Arc, time::Duration};
+use tokio::{select, sync::broadcast::Receiver, time};
+use tracing::{debug, error, info, instrument};
 
 mod delete_service;
 mod plan_update;
+mod queue;
+mod report_deployment;
+mod scheduled_run;
 mod update_service;
 
 pub use delete_service::DeleteService;
 pub use plan_update::PlanUpdate;
+pub use queue::{CancelError, JobQueue, Status};
+pub use report_deployment::ReportDeployment;
+pub use scheduled_run::ScheduledRun;
 pub use update_service::UpdateService;
 
-pub type JobQueue = Queue<Box<dyn Job>>;
+/// The tree in the state database where the jobs are persisted
+const TREE: &str = "agent:jobs";
+
+/// How often to check for finished jobs that have expired
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 static STATIC_INSTANCE: Lazy<Arc<JobQueue>> = Lazy::new(|| Arc::from(JobQueue::new()));
 
-/// Dispatch a job to one of the processors
-pub fn dispatch(job: impl Job + 'static) {
-    STATIC_INSTANCE.push(Box::new(job));
+tokio::task_local! {
+    /// The attempt at running a job that the current task is part of
+    static CURRENT: Attempt;
+}
+
+/// A single attempt at running a job
+#[derive(Clone, Copy, Debug)]
+pub struct Attempt {
+    /// The ID of the job being run
+    pub id: u64,
+    /// Whether the job will fail for good if this attempt fails
+    pub last: bool,
+}
+
+/// Persist the queue in the deployer's state database, restoring any jobs that were
+/// interrupted by the last shutdown. Must be called before any jobs are dispatched.
+pub fn initialize() -> anyhow::Result<()> {
+    let tree = deployer::instance().tree(TREE)?;
+    let restored = STATIC_INSTANCE.restore(tree)?;
+    info!(count = restored, "restored job queue");
+
+    Ok(())
+}
+
+/// Dispatch a job to one of the processors, returning the job's ID
+pub fn dispatch(job: impl Job + 'static) -> u64 {
+    STATIC_INSTANCE.push(Box::new(job))
+}
+
+/// Dispatch a job that only runs once the jobs it depends on have finished, returning the
+/// job's ID
+pub fn dispatch_after(job: impl Job + 'static, depends_on: Vec<u64>) -> u64 {
+    STATIC_INSTANCE.push_after(Box::new(job), depends_on)
+}
+
+/// Run a future on behalf of a job, any jobs it dispatches are recorded as the job's children
+pub async fn scope<F: Future>(attempt: Option<Attempt>, f: F) -> F::Output {
+    match attempt {
+        Some(attempt) => CURRENT.scope(attempt, f).await,
+        None => f.await,
+    }
+}
+
+/// The attempt at running a job that the current task is part of
+pub fn attempt() -> Option<Attempt> {
+    CURRENT.try_with(|attempt| *attempt).ok()
+}
+
+/// The ID of the job the current task is running on behalf of
+pub fn current() -> Option<u64> {
+    attempt().map(|attempt| attempt.id)
+}
+
+/// Whether the job the current task is running will be retried if it fails with the error
+pub fn will_retry<E: Retryable + ?Sized>(error: &E) -> bool {
+    error.is_retryable() && attempt().is_some_and(|attempt| !attempt.last)
 }
 
 /// Retrieve an instance of the queue
@@ -25,18 +92,156 @@ pub fn instance() -> Arc<JobQueue> {
     STATIC_INSTANCE.clone()
 }
 
+/// Periodically remove finished jobs once they are older than the retention period
+#[instrument(skip(stop))]
+pub async fn prune(mut stop: Receiver<()>) {
+    let retention = config::instance().agent.job_retention();
+    let retention =
+        chrono::Duration::from_std(retention).unwrap_or_else(|_| chrono::Duration::max_value());
+    let mut interval = time::interval(PRUNE_INTERVAL);
+
+    select! {
+        _ = async {
+            loop {
+                interval.tick().await;
+                match STATIC_INSTANCE.prune(retention) {
+                    Ok(removed) => debug!(%removed, "pruned expired jobs"),
+                    Err(e) => error!(error = %e, "failed to prune expired jobs"),
+                }
+            }
+        } => {}
+        _ = stop.recv() => {
+            info!("stopped pruning jobs");
+        }
+    }
+}
+
+/// Recreate a persisted job from its name and arguments
+fn restore(name: &str, job: Value) -> serde_json::Result<Box<dyn Job>> {
+    let job: Box<dyn Job> = match name {
+        "delete_service" => Box::new(serde_json::from_value::<DeleteService>(job)?),
+        "plan_update" => Box::new(serde_json::from_value::<PlanUpdate>(job)?),
+        "report_deployment" => Box::new(serde_json::from_value::<ReportDeployment>(job)?),
+        "scheduled_run" => Box::new(serde_json::from_value::<ScheduledRun>(job)?),
+        "update_service" => Box::new(serde_json::from_value::<UpdateService>(job)?),
+        _ => return Err(serde::de::Error::custom(format!("unknown job {:?}", name))),
+    };
+    Ok(job)
+}
+
+/// Why a job failed
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct Error {
+    message: String,
+    retryable: bool,
+}
+
+impl Error {
+    /// Create a new error with the given message, the job will not be retried
+    pub fn new<S: Into<String>>(message: S) -> Error {
+        Error {
+            message: message.into(),
+            retryable: false,
+        }
+    }
+
+    /// Create a new error from the error that caused the job to fail, the job is retried if
+    /// the cause was transient
+    pub fn from_source<E: Retryable + ToString + ?Sized>(error: &E) -> Error {
+        Error {
+            message: error.to_string(),
+            retryable: error.is_retryable(),
+        }
+    }
+}
+
+/// Classify errors as transient, where the job may succeed if it is run again, or fatal
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+impl Retryable for deployer::Error {
+    fn is_retryable(&self) -> bool {
+        matches!(self, deployer::Error::Timeout(_) | deployer::Error::Http(_))
+    }
+}
+
+impl Retryable for vault::Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            vault::Error::Timeout(_) => true,
+            vault::Error::Status { code, .. } => *code == 429 || *code >= 500,
+            vault::Error::Unknown(e) => e.is_connect() || e.is_request(),
+            _ => false,
+        }
+    }
+}
+
+impl Retryable for redis::RedisError {
+    fn is_retryable(&self) -> bool {
+        self.is_timeout() || self.is_connection_refusal() || self.is_connection_dropped()
+    }
+}
+
+impl Retryable for git2::Error {
+    fn is_retryable(&self) -> bool {
+        matches!(self.class(), git2::ErrorClass::Net | git2::ErrorClass::Http)
+    }
+}
+
+/// Convert a job to a form that can be persisted, implemented for all serializable jobs
+pub trait Persist {
+    fn persist(&self) -> serde_json::Result<Value>;
+}
+
+impl<T: Serialize> Persist for T {
+    fn persist(&self) -> serde_json::Result<Value> {
+        serde_json::to_value(self)
+    }
+}
+
 /// A job that can be run in a separate thread
 #[async_trait]
-pub trait Job: Send + Sync {
-    /// Run the job
-    async fn run(&self);
+pub trait Job: Any + Persist + Send + Sync {
+    /// Run the job. If it fails with a retryable error, it is run again after a delay until
+    /// it runs out of retries.
+    async fn run(&self) -> Result<(), Error>;
 
     /// The name of the job
     fn name<'a>(&self) -> &'a str;
+
+    /// The service the job operates on, only one job is run for a service at a time
+    fn service(&self) -> Option<&ServiceName> {
+        None
+    }
+
+    /// Take over from a job for the same service that is still waiting to run so only this
+    /// job needs to run. Returns whether the queued job can be replaced.
+    fn merge(&mut self, _queued: &mut dyn Job) -> bool {
+        false
+    }
+
+    /// Whether the job is skipped when a job it depends on doesn't succeed, otherwise it runs
+    /// once all of them have finished
+    fn requires_dependencies(&self) -> bool {
+        true
+    }
+
+    /// Called once the job will not be run again, either because it finished, failed for
+    /// good, or was cancelled or skipped before it could run
+    fn finished(&self, _succeeded: bool) {}
 }
 
-/// Log error and stop execution from within a job. A notification
-/// with the specified event and args will also be sent.
+/// Log error and stop execution from within a job, failing it. A notification
+/// with the specified event and args will also be sent, unless the error is
+/// transient and the job will be retried.
 ///
 /// This is intended to be called from a macro within the function so extra
 /// arguments are automatically passed in.
@@ -49,16 +254,75 @@ macro_rules! fail_notify {
             Ok(v) => v,
             Err(e) => {
                 use $crate::notifier::{notify, Event, State};
+                use $crate::processor::jobs::{will_retry, Error as JobError};
                 use std::error::Error;
 
-                match e.source() {
-                    Some(s) => tracing::error!(error = %e, source = %s, $message),
-                    None => tracing::error!(error = %e, $message),
+                if will_retry(&e) {
+                    match e.source() {
+                        Some(s) => tracing::warn!(error = %e, source = %s, "{}, retrying", $message),
+                        None => tracing::warn!(error = %e, "{}, retrying", $message),
+                    }
+                } else {
+                    match e.source() {
+                        Some(s) => tracing::error!(error = %e, source = %s, $message),
+                        None => tracing::error!(error = %e, $message),
+                    }
+
+                    notify(Event::$event( $( $arg ),*, State::Failure(e.to_string()) )).await;
                 }
 
-                notify(Event::$event( $( $arg ),*, State::Failure(e.to_string()) )).await;
-                return;
+                return Err(JobError::from_source(&e));
             }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{restore, Error, Job, Retryable, ScheduledRun, UpdateService};
+    use crate::{deployer, service::Service};
+    use bollard::errors::Error as BollardError;
+    use chrono::Utc;
+    use redis::RedisError;
+    use std::io;
+
+    /// Persist a job and recreate it, ensuring nothing was lost along the way
+    fn roundtrip(job: &dyn Job) {
+        let persisted = job.persist().unwrap();
+        let restored = restore(job.name(), persisted.clone()).unwrap();
+
+        assert_eq!(restored.name(), job.name());
+        assert_eq!(restored.persist().unwrap(), persisted);
+    }
+
+    #[test]
+    fn restore_jobs() {
+        let raw = std::fs::read("./example-service.toml").unwrap();
+        let config = Service::from_slice(&raw).unwrap();
+
+        roundtrip(&UpdateService::with_commit(
+            config,
+            "wafflehacks/cms".into(),
+            "0123456789abcdef",
+        ));
+        roundtrip(&ScheduledRun::new("wafflehacks/cleanup".into(), Utc::now()));
+    }
+
+    #[test]
+    fn restore_unknown() {
+        assert!(restore("launch_rockets", serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn classify_errors() {
+        let timeout = deployer::Error::from(BollardError::RequestTimeoutError);
+        assert!(timeout.is_retryable());
+        assert!(Error::from_source(&timeout).is_retryable());
+        assert!(!deployer::Error::Unhealthy.is_retryable());
+
+        let dropped = RedisError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(dropped.is_retryable());
+
+        assert!(!Error::new("job exited with status code 1").is_retryable());
+    }
+}
@@ -1,15 +1,21 @@
-use super::{DeleteService, Job, UpdateService};
+use super::{DeleteService, Error, Job, ReportDeployment, UpdateService};
 use crate::{
     fail_notify,
     git::{self, Action},
     notifier::{self, Event, State},
-    service::Service,
+    processor::plan,
+    service::{Profile, Service},
 };
 use async_trait::async_trait;
-use std::{ffi::OsStr, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::PathBuf,
+};
 use tracing::{error, info, instrument, warn};
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PlanUpdate {
     base_path: PathBuf,
     before: String,
@@ -43,7 +49,7 @@ impl Job for PlanUpdate {
         skip(self),
         fields(before = %self.short_before(), after = %self.short_after(), name = %self.name())
     )]
-    async fn run(&self) {
+    async fn run(&self) -> Result<(), Error> {
         macro_rules! fail {
             ($result:expr) => {
                 fail_notify!(deployment, &self.after; $result; "an error occurred while planning deployment")
@@ -59,8 +65,11 @@ impl Job for PlanUpdate {
                 .await
         );
 
-        // Spawn jobs for the changed files
+        // Find the services that need to change
+        let mut updates = HashMap::new();
+        let mut deletions = Vec::new();
         let mut parse_failures = Vec::new();
+        let mut profiles = HashSet::new();
         for diff in files {
             if diff.binary {
                 // Ignore binary files
@@ -82,12 +91,20 @@ impl Job for PlanUpdate {
                 continue;
             }
 
+            // Profiles aren't deployed themselves, only the services that include them
+            if let Some(profile) = Profile::name(&diff.path) {
+                info!(path = %diff.path.display(), %profile, "profile changed");
+                profiles.insert(profile);
+                continue;
+            }
+
             let name = Service::name(diff.path.as_path());
 
             match diff.action {
                 Action::Modified => {
                     // Parse the configuration
-                    let config = match Service::parse(self.base_path.join(&diff.path)).await {
+                    let path = self.base_path.join(&diff.path);
+                    let config = match Service::load(&self.base_path, &path).await {
                         Ok(c) => c,
                         Err(e) => {
                             let displayable = diff.path.display();
@@ -101,28 +118,294 @@ impl Job for PlanUpdate {
                         }
                     };
 
-                    // Spawn update job
                     info!(path = %diff.path.display(), name = %name, "updating service");
-                    super::dispatch(UpdateService::new(config, name));
+                    updates.insert(name.proper.clone(), (config, name));
                 }
                 Action::Deleted => {
-                    // Spawn delete job
                     info!(path = %diff.path.display(), name = %name, "deleting service");
-                    super::dispatch(DeleteService::new(name));
+                    deletions.push(name);
                 }
                 _ => unreachable!(),
             }
         }
 
-        let state = if parse_failures.is_empty() {
-            State::Success
-        } else {
-            State::Failure(format!("unable to parse: {}", parse_failures.join(", ")))
+        // Services that include a changed profile need to be redeployed even if they didn't
+        // change, what they include is taken from the commit being deployed
+        let dependents = fail!(plan::dependents(&self.after, &profiles).await);
+        for path in dependents {
+            let name = Service::name(&path);
+            if updates.contains_key(&name.proper)
+                || deletions.iter().any(|d| d.proper == name.proper)
+            {
+                continue;
+            }
+
+            match Service::load(&self.base_path, &self.base_path.join(&path)).await {
+                Ok(config) => {
+                    info!(%name, "updating service for changed profile");
+                    updates.insert(name.proper.clone(), (config, name));
+                }
+                Err(e) => {
+                    let displayable = path.display();
+                    error!(error = %e, path = %displayable, "failed to parse service configuration");
+                    parse_failures.push(displayable.to_string());
+                }
+            }
+        }
+
+        // Services that depend on each other must be updated in order
+        let dependencies = updates
+            .iter()
+            .map(|(name, (config, _))| (name.clone(), config.depends_on.clone()))
+            .collect();
+        let graph = match Graph::new(&dependencies) {
+            Ok(graph) => graph,
+            Err(cycle) => {
+                let cycle = cycle.join(", ");
+                error!(services = %cycle, "found a dependency cycle");
+
+                let message = format!("dependency cycle between: {}", cycle);
+                notifier::notify(Event::deployment(
+                    &self.after,
+                    State::Failure(message.clone()),
+                ))
+                .await;
+                return Err(Error::new(message));
+            }
         };
-        notifier::notify(Event::deployment(&self.after, state)).await;
+
+        // Deletions don't need to wait on anything
+        for name in deletions {
+            super::dispatch(DeleteService::new(name));
+        }
+
+        // Every update is queued up front, the queue holds each one back until the updates
+        // it depends on have succeeded and skips it if any of them fail
+        let mut ids = HashMap::new();
+        for service in graph.order() {
+            let (config, name) = match updates.remove(&service) {
+                Some(update) => update,
+                None => continue,
+            };
+
+            let depends_on = config
+                .depends_on
+                .iter()
+                .filter_map(|dependency| ids.get(dependency).copied())
+                .collect();
+            let id = super::dispatch_after(UpdateService::new(config, name), depends_on);
+            ids.insert(service, id);
+        }
+
+        // The outcome of the deployment is reported once all the updates have finished
+        let mut updates = ids.into_iter().collect::<Vec<_>>();
+        updates.sort();
+        let depends_on = updates.iter().map(|(_, id)| *id).collect();
+        super::dispatch_after(
+            ReportDeployment::new(&*self.after, updates, parse_failures),
+            depends_on,
+        );
+
+        Ok(())
     }
 
     fn name<'a>(&self) -> &'a str {
         "plan_update"
     }
 }
+
+/// The dependencies between the services being updated. Any dependencies on services that are
+/// not being updated are assumed to already be satisfied.
+#[derive(Clone, Debug)]
+struct Graph {
+    /// The number of dependencies each service is still waiting on
+    waiting: HashMap<String, usize>,
+    /// The services that depend on each service
+    dependents: HashMap<String, Vec<String>>,
+}
+
+impl Graph {
+    /// Build the graph from each service's dependencies, failing with the services that are
+    /// part of a cycle
+    fn new(dependencies: &HashMap<String, Vec<String>>) -> Result<Graph, Vec<String>> {
+        let mut waiting = HashMap::new();
+        let mut dependents = HashMap::<String, Vec<String>>::new();
+        for (name, depends_on) in dependencies {
+            let depends_on = depends_on
+                .iter()
+                .filter(|d| dependencies.contains_key(*d))
+                .collect::<HashSet<_>>();
+
+            waiting.insert(name.clone(), depends_on.len());
+            for dependency in depends_on {
+                dependents
+                    .entry(dependency.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+        }
+
+        let graph = Graph {
+            waiting,
+            dependents,
+        };
+
+        // Everything can only be reached if there are no cycles
+        let mut check = graph.clone();
+        let mut ready = check.ready();
+        while let Some(name) = ready.pop() {
+            ready.extend(check.complete(&name));
+        }
+
+        let blocked = check
+            .waiting
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, _)| name)
+            .collect::<HashSet<_>>();
+        if blocked.is_empty() {
+            return Ok(graph);
+        }
+
+        // Services that only depend on a cycle are blocked too, but aren't part of it
+        let mut cycle = blocked
+            .iter()
+            .filter(|name| graph.reaches(name, name))
+            .cloned()
+            .collect::<Vec<_>>();
+        cycle.sort();
+        Err(cycle)
+    }
+
+    /// Whether the target depends on the service, directly or transitively
+    fn reaches(&self, service: &str, target: &str) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![service];
+        while let Some(name) = stack.pop() {
+            for dependent in self.dependents.get(name).into_iter().flatten() {
+                if dependent == target {
+                    return true;
+                }
+                if visited.insert(dependent.as_str()) {
+                    stack.push(dependent);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// The services that are not waiting on anything
+    fn ready(&self) -> Vec<String> {
+        let mut ready = self
+            .waiting
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        ready.sort();
+        ready
+    }
+
+    /// Mark a service as successfully updated, returning the services that are now ready
+    fn complete(&mut self, name: &str) -> Vec<String> {
+        let mut ready = Vec::new();
+        for dependent in self.dependents.get(name).into_iter().flatten() {
+            if let Some(count) = self.waiting.get_mut(dependent) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(dependent.clone());
+                }
+            }
+        }
+
+        ready
+    }
+
+    /// Every service ordered so that each one comes after the services it depends on
+    fn order(mut self) -> Vec<String> {
+        let mut order = Vec::new();
+        let mut ready = self.ready();
+        ready.reverse();
+        while let Some(name) = ready.pop() {
+            let mut next = self.complete(&name);
+            next.sort();
+            ready.extend(next.into_iter().rev());
+            order.push(name);
+        }
+
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Graph;
+    use std::collections::HashMap;
+
+    fn graph(edges: &[(&str, &[&str])]) -> Result<Graph, Vec<String>> {
+        let dependencies = edges
+            .iter()
+            .map(|(name, depends_on)| {
+                let depends_on = depends_on.iter().map(|d| d.to_string()).collect();
+                (name.to_string(), depends_on)
+            })
+            .collect::<HashMap<_, _>>();
+        Graph::new(&dependencies)
+    }
+
+    #[test]
+    fn ordering() {
+        let mut graph = graph(&[
+            ("api", &["auth", "proxy"]),
+            ("auth", &["proxy"]),
+            ("proxy", &[]),
+            ("web", &["unchanged"]),
+        ])
+        .unwrap();
+
+        assert_eq!(graph.ready(), vec!["proxy", "web"]);
+        assert_eq!(graph.complete("web"), Vec::<String>::new());
+        assert_eq!(graph.complete("proxy"), vec!["auth"]);
+        assert_eq!(graph.complete("auth"), vec!["api"]);
+    }
+
+    #[test]
+    fn order() {
+        let graph = graph(&[
+            ("api", &["auth", "proxy"]),
+            ("auth", &["proxy"]),
+            ("proxy", &[]),
+            ("web", &["unchanged"]),
+        ])
+        .unwrap();
+
+        assert_eq!(graph.order(), vec!["proxy", "auth", "api", "web"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let cycle = graph(&[
+            ("a", &["b"]),
+            ("b", &["c"]),
+            ("c", &["a"]),
+            ("d", &[]),
+            ("e", &["a"]),
+        ])
+        .unwrap_err();
+        assert_eq!(cycle, vec!["a", "b", "c"]);
+
+        assert_eq!(graph(&[("a", &["a"])]).unwrap_err(), vec!["a"]);
+
+        // Only the services in a cycle are reported, not the ones between cycles
+        let cycles = graph(&[
+            ("a", &["b"]),
+            ("b", &["a", "x"]),
+            ("x", &["y"]),
+            ("y", &["z"]),
+            ("z", &["y"]),
+        ])
+        .unwrap_err();
+        assert_eq!(cycles, vec!["a", "b", "y", "z"]);
+    }
+}
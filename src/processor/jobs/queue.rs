@@ -0,0 +1,1179 @@
+use super::{current, restore, Error, Job};
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::Tree;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+};
+use tokio::{
+    select,
+    sync::Notify,
+    time::{self, Instant},
+};
+use tracing::{debug, info, warn};
+
+/// A queue of jobs where jobs for the same service run one at a time. Jobs for different
+/// services can still be run in parallel. Jobs can also wait for other jobs to finish before
+/// they are run. Once a store is attached, every job is persisted so that it survives restarts.
+#[derive(Default)]
+pub struct JobQueue {
+    state: Mutex<State>,
+    /// Woken whenever a job may have become available to run
+    available: Notify,
+    /// Where the job records are persisted
+    store: OnceCell<Tree>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: VecDeque<Queued>,
+    /// The services that currently have a job running
+    running: HashSet<String>,
+    /// The jobs that are currently running
+    active: HashSet<u64>,
+    next_id: u64,
+}
+
+/// A job that was taken from the queue
+pub struct Queued {
+    pub id: u64,
+    pub job: Box<dyn Job>,
+    /// The number of times the job has already been retried
+    pub retries: u32,
+    /// When the job can next be run if it is waiting to be retried
+    not_before: Option<Instant>,
+    /// The jobs that must finish before this job can run
+    depends_on: Vec<u64>,
+}
+
+impl Queued {
+    fn new(id: u64, job: Box<dyn Job>, depends_on: Vec<u64>) -> Queued {
+        Queued {
+            id,
+            job,
+            retries: 0,
+            not_before: None,
+            depends_on,
+        }
+    }
+
+    /// Whether the job is waiting to be retried
+    fn delayed(&self, now: Instant) -> bool {
+        self.not_before.is_some_and(|at| at > now)
+    }
+}
+
+/// The persisted form of a job
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub id: u64,
+    /// The name of the kind of job
+    pub name: String,
+    /// The service the job operates on, if any
+    pub service: Option<String>,
+    pub status: Status,
+    /// The job that dispatched this job, if any
+    pub parent: Option<u64>,
+    /// The jobs that must finish before this job can run
+    #[serde(default)]
+    pub depends_on: Vec<u64>,
+    /// The worker that ran the job
+    pub worker: Option<u32>,
+    /// The job's arguments, used to recreate it if the agent restarts before it finishes
+    pub job: Value,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Why the job failed, or why its last attempt failed if it is waiting to be retried
+    pub error: Option<String>,
+    /// The job that took over from this job, if it was replaced before it could finish
+    #[serde(default)]
+    pub replaced_by: Option<u64>,
+    /// The number of times the job has been retried
+    #[serde(default)]
+    pub retries: u32,
+    /// When the job will next be retried
+    #[serde(default)]
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+/// Where a job is in its lifecycle
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    /// A job it depends on did not succeed
+    Skipped,
+}
+
+impl Status {
+    /// Whether the job will no longer run
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Status::Done | Status::Failed | Status::Cancelled | Status::Skipped
+        )
+    }
+}
+
+/// Why a job could not be cancelled
+#[derive(Debug, thiserror::Error)]
+pub enum CancelError {
+    #[error("job not found")]
+    NotFound,
+    #[error("only pending jobs can be cancelled, job is {0:?}")]
+    NotPending(Status),
+}
+
+impl JobQueue {
+    /// Create a new empty queue
+    pub fn new() -> JobQueue {
+        JobQueue::default()
+    }
+
+    /// Persist the queue to the given tree, restoring any jobs that were pending or running
+    /// when the agent last stopped. This must be called before any jobs are pushed. Returns
+    /// the number of jobs that were restored.
+    pub fn restore(&self, tree: Tree) -> sled::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut restored = 0;
+        let mut records = HashMap::new();
+        for entry in tree.iter() {
+            let (key, value) = entry?;
+            let id = id_from_key(&key);
+            state.next_id = state.next_id.max(id + 1);
+
+            let mut record = match serde_json::from_slice::<Record>(&value) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!(%id, error = %e, "discarding invalid job record");
+                    tree.remove(key)?;
+                    continue;
+                }
+            };
+            if record.status.is_finished() {
+                records.insert(id, record.status);
+                continue;
+            }
+
+            match restore(&record.name, record.job.clone()) {
+                Ok(job) => {
+                    if record.status == Status::Running {
+                        info!(%id, name = %record.name, "resuming interrupted job");
+                    }
+
+                    let delay = record
+                        .retry_at
+                        .and_then(|at| (at - Utc::now()).to_std().ok());
+                    state.pending.push_back(Queued {
+                        retries: record.retries,
+                        not_before: delay.map(|delay| Instant::now() + delay),
+                        ..Queued::new(id, job, record.depends_on)
+                    });
+                    records.insert(id, Status::Pending);
+                    restored += 1;
+                }
+                Err(e) => {
+                    warn!(%id, name = %record.name, error = %e, "failed to restore job");
+                    record.status = Status::Failed;
+                    record.finished_at = Some(Utc::now());
+                    record.error = Some(format!("failed to restore job: {}", e));
+                    save(&tree, &record);
+                    records.insert(id, Status::Failed);
+                }
+            }
+        }
+
+        // Dependencies that finished while the agent was running are already resolved, but the
+        // ones that didn't succeed still need to skip the jobs that require them. Records that
+        // were pruned must have finished long ago, so they're assumed to have succeeded.
+        let mut failed = HashSet::new();
+        for queued in state.pending.iter_mut() {
+            queued.depends_on.retain(|id| match records.get(id) {
+                Some(Status::Pending) => true,
+                Some(Status::Done) | None => false,
+                Some(_) => {
+                    failed.insert(*id);
+                    true
+                }
+            });
+        }
+
+        self.store.set(tree).ok();
+        drop(state);
+
+        for id in failed {
+            self.resolve(id, false);
+        }
+
+        self.available.notify_waiters();
+        Ok(restored)
+    }
+
+    /// Add a job to the back of the queue, returning its ID. If the last job for the same
+    /// service is still waiting to run and can be merged into the new job, the new job takes
+    /// its place instead.
+    pub fn push(&self, job: Box<dyn Job>) -> u64 {
+        self.push_after(job, Vec::new())
+    }
+
+    /// Add a job to the back of the queue that only runs once all the jobs it depends on have
+    /// finished, returning its ID. Jobs for the same service are merged like with [`push`].
+    ///
+    /// [`push`]: JobQueue::push
+    pub fn push_after(&self, mut job: Box<dyn Job>, depends_on: Vec<u64>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        // Dependencies may have already finished by the time the job is queued
+        let mut failed = None;
+        let mut outstanding = Vec::new();
+        for dependency in depends_on {
+            match self.outstanding(&state, dependency) {
+                Ok(dependency) if !outstanding.contains(&dependency) => {
+                    outstanding.push(dependency)
+                }
+                Ok(_) | Err(Status::Done) => {}
+                Err(_) => failed = Some(dependency),
+            }
+        }
+        let depends_on = outstanding;
+
+        if let Some(store) = self.store.get() {
+            save(store, &Record::new(id, job.as_ref(), depends_on.clone()));
+        }
+
+        if let Some(dependency) = failed.filter(|_| job.requires_dependencies()) {
+            drop(state);
+            self.skip(id, job.as_ref(), dependency);
+            return id;
+        }
+
+        // Only the latest queued job for the service can be replaced, otherwise the jobs
+        // would run out of order
+        if let Some(service) = job.service().map(|s| s.proper.clone()) {
+            let latest = state
+                .pending
+                .iter()
+                .rposition(|queued| queued.job.service().map(|s| &s.proper) == Some(&service));
+            if let Some(index) = latest {
+                if job.merge(state.pending[index].job.as_mut()) {
+                    debug!(name = job.name(), %service, "coalesced with queued job");
+
+                    // The new job also has to wait for everything the replaced job was waiting on
+                    let mut depends_on = depends_on;
+                    for dependency in &state.pending[index].depends_on {
+                        if !depends_on.contains(dependency) {
+                            depends_on.push(*dependency);
+                        }
+                    }
+                    self.update(id, |record| record.depends_on = depends_on.clone());
+
+                    let replaced = std::mem::replace(
+                        &mut state.pending[index],
+                        Queued::new(id, job, depends_on),
+                    );
+                    self.update(replaced.id, |record| {
+                        record.status = Status::Cancelled;
+                        record.finished_at = Some(Utc::now());
+                        record.error = Some(format!("replaced by job {}", id));
+                        record.replaced_by = Some(id);
+                    });
+                    self.replace_dependency(&mut state.pending, replaced.id, id);
+                    return id;
+                }
+            }
+        }
+
+        state.pending.push_back(Queued::new(id, job, depends_on));
+        drop(state);
+
+        self.available.notify_waiters();
+        id
+    }
+
+    /// Wait for the next job that can be run by the worker. The job's service is locked until
+    /// the job is marked as finished.
+    pub async fn pop(&self, worker: u32) -> Queued {
+        loop {
+            // Created before checking so no notifications are missed in between
+            let available = self.available.notified();
+
+            if let Some(queued) = self.try_pop(worker) {
+                return queued;
+            }
+
+            match self.next_retry() {
+                Some(at) => select! {
+                    _ = available => {}
+                    _ = time::sleep_until(at) => {}
+                },
+                None => available.await,
+            }
+        }
+    }
+
+    /// Take the first job whose service is not already running a job, that isn't waiting on a
+    /// job to be retried, and whose dependencies have all finished
+    fn try_pop(&self, worker: u32) -> Option<Queued> {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            pending, running, ..
+        } = &mut *state;
+
+        let now = Instant::now();
+        let mut blocked = HashSet::new();
+        let index = pending.iter().position(|queued| {
+            let service = queued.job.service().map(|s| s.proper.as_str());
+            if queued.delayed(now) || !queued.depends_on.is_empty() {
+                // Newer jobs for the service have to wait until this one has run
+                blocked.extend(service);
+                return false;
+            }
+
+            match service {
+                Some(service) => !running.contains(service) && !blocked.contains(service),
+                None => true,
+            }
+        })?;
+        let queued = pending.remove(index)?;
+
+        if let Some(service) = queued.job.service() {
+            running.insert(service.proper.clone());
+        }
+        state.active.insert(queued.id);
+        self.update(queued.id, |record| {
+            record.status = Status::Running;
+            record.worker = Some(worker);
+            record.started_at = Some(Utc::now());
+            record.retry_at = None;
+        });
+
+        Some(queued)
+    }
+
+    /// When the next job waiting to be retried can be run
+    fn next_retry(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        state
+            .pending
+            .iter()
+            .filter_map(|q| q.not_before)
+            .filter(|at| *at > now)
+            .min()
+    }
+
+    /// Record the outcome of a job and release the lock on its service so the next job for it
+    /// can be run
+    pub fn finish(&self, queued: &Queued, result: &Result<(), Error>) {
+        queued.job.finished(result.is_ok());
+
+        self.update(queued.id, |record| {
+            record.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => record.status = Status::Done,
+                Err(e) => {
+                    record.status = Status::Failed;
+                    record.error = Some(e.to_string());
+                }
+            }
+        });
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(service) = queued.job.service() {
+            state.running.remove(&service.proper);
+        }
+        state.active.remove(&queued.id);
+        drop(state);
+
+        self.resolve(queued.id, result.is_ok());
+    }
+
+    /// Put a job that failed with a transient error back in the queue to be run again once the
+    /// delay has passed. The job stays ahead of any newer jobs for its service, unless the
+    /// newest of them can take over from it.
+    pub fn retry(&self, mut queued: Queued, error: &Error, delay: std::time::Duration) {
+        let mut state = self.state.lock().unwrap();
+        let service = queued.job.service().map(|s| s.proper.clone());
+        if let Some(service) = &service {
+            state.running.remove(service);
+        }
+        state.active.remove(&queued.id);
+
+        let newer = service.as_ref().and_then(|service| {
+            state
+                .pending
+                .iter()
+                .position(|pending| pending.job.service().map(|s| &s.proper) == Some(service))
+        });
+        let latest = service.as_ref().and_then(|service| {
+            state
+                .pending
+                .iter()
+                .rposition(|pending| pending.job.service().map(|s| &s.proper) == Some(service))
+        });
+
+        if let Some(latest) = latest {
+            let replacement = &mut state.pending[latest];
+            if replacement.job.merge(queued.job.as_mut()) {
+                debug!(
+                    id = queued.id,
+                    replacement = replacement.id,
+                    "retry replaced by newer job"
+                );
+
+                let id = replacement.id;
+                self.update(queued.id, |record| {
+                    record.status = Status::Cancelled;
+                    record.finished_at = Some(Utc::now());
+                    record.error = Some(format!("{}, replaced by job {}", error, id));
+                    record.replaced_by = Some(id);
+                });
+                self.replace_dependency(&mut state.pending, queued.id, id);
+
+                drop(state);
+                self.available.notify_waiters();
+                return;
+            }
+        }
+
+        queued.retries += 1;
+        queued.not_before = Some(Instant::now() + delay);
+        let retry_at =
+            Utc::now() + Duration::from_std(delay).unwrap_or_else(|_| Duration::max_value());
+        self.update(queued.id, |record| {
+            record.status = Status::Pending;
+            record.error = Some(error.to_string());
+            record.retries = queued.retries;
+            record.retry_at = Some(retry_at);
+        });
+
+        match newer {
+            Some(index) => state.pending.insert(index, queued),
+            None => state.pending.push_back(queued),
+        }
+        drop(state);
+
+        self.available.notify_waiters();
+    }
+
+    /// Remove a job from the queue before it runs
+    pub fn cancel(&self, id: u64) -> Result<Record, CancelError> {
+        let mut state = self.state.lock().unwrap();
+
+        let index = state.pending.iter().position(|queued| queued.id == id);
+        let queued = match index.and_then(|index| state.pending.remove(index)) {
+            Some(queued) => queued,
+            None => {
+                return Err(match self.record(id) {
+                    Some(record) => CancelError::NotPending(record.status),
+                    None => CancelError::NotFound,
+                })
+            }
+        };
+        drop(state);
+
+        queued.job.finished(false);
+        self.update(id, |record| {
+            record.status = Status::Cancelled;
+            record.finished_at = Some(Utc::now());
+        });
+        self.resolve(id, false);
+
+        self.record(id).ok_or(CancelError::NotFound)
+    }
+
+    /// The services that have a job waiting to run or running
+    pub fn busy(&self) -> HashSet<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .pending
+            .iter()
+            .filter_map(|queued| queued.job.service())
+            .map(|service| service.proper.clone())
+            .chain(state.running.iter().cloned())
+            .collect()
+    }
+
+    /// Get the records of the jobs that are waiting to run or running
+    pub fn unfinished(&self) -> Vec<Record> {
+        let state = self.state.lock().unwrap();
+        state
+            .pending
+            .iter()
+            .map(|queued| queued.id)
+            .chain(state.active.iter().copied())
+            .filter_map(|id| self.record(id))
+            .collect()
+    }
+
+    /// Get the record of a job
+    pub fn record(&self, id: u64) -> Option<Record> {
+        let raw = self.store.get()?.get(key(id)).ok()??;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    /// Get the records of all the jobs that haven't been pruned, oldest first
+    pub fn records(&self) -> sled::Result<Vec<Record>> {
+        let store = match self.store.get() {
+            Some(store) => store,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut records = Vec::new();
+        for entry in store.iter() {
+            let (_, value) = entry?;
+            if let Ok(record) = serde_json::from_slice(&value) {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Remove the records of jobs that finished longer than the retention period ago,
+    /// returning how many were removed
+    pub fn prune(&self, retention: Duration) -> sled::Result<usize> {
+        let store = match self.store.get() {
+            Some(store) => store,
+            None => return Ok(0),
+        };
+        let cutoff = Utc::now() - retention;
+
+        let mut removed = 0;
+        for entry in store.iter() {
+            let (key, value) = entry?;
+            let expired = serde_json::from_slice::<Record>(&value)
+                .ok()
+                .and_then(|record| record.finished_at)
+                .is_some_and(|finished| finished <= cutoff);
+            if expired {
+                store.remove(key)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Release the jobs waiting on a job that will no longer run. If the job didn't succeed,
+    /// the jobs that require it are skipped along with anything that requires them.
+    fn resolve(&self, id: u64, succeeded: bool) {
+        let mut skipped = Vec::new();
+        let mut finished = vec![(id, succeeded)];
+
+        let mut state = self.state.lock().unwrap();
+        while let Some((id, succeeded)) = finished.pop() {
+            let mut index = 0;
+            while index < state.pending.len() {
+                let queued = &mut state.pending[index];
+                if !queued.depends_on.contains(&id) {
+                    index += 1;
+                    continue;
+                }
+
+                queued.depends_on.retain(|d| *d != id);
+                if succeeded || !queued.job.requires_dependencies() {
+                    let depends_on = queued.depends_on.clone();
+                    self.update(queued.id, |record| record.depends_on = depends_on);
+                    index += 1;
+                } else if let Some(queued) = state.pending.remove(index) {
+                    finished.push((queued.id, false));
+                    skipped.push((queued, id));
+                }
+            }
+        }
+        drop(state);
+
+        for (queued, dependency) in skipped {
+            self.skip(queued.id, queued.job.as_ref(), dependency);
+        }
+
+        self.available.notify_waiters();
+    }
+
+    /// Mark a job as skipped because a dependency didn't succeed
+    fn skip(&self, id: u64, job: &dyn Job, dependency: u64) {
+        info!(
+            %id,
+            name = job.name(),
+            %dependency,
+            "skipping job as a dependency did not succeed"
+        );
+
+        job.finished(false);
+        self.update(id, |record| {
+            record.status = Status::Skipped;
+            record.finished_at = Some(Utc::now());
+            record.error = Some(format!("dependency {} did not succeed", dependency));
+        });
+    }
+
+    /// Find the job that still has to run for a dependency, following any jobs that replaced
+    /// it. If it already finished, the status it finished with is returned instead. Records
+    /// that were pruned must have finished long ago, so they're assumed to have succeeded.
+    fn outstanding(&self, state: &State, mut id: u64) -> Result<u64, Status> {
+        loop {
+            if state.active.contains(&id) || state.pending.iter().any(|queued| queued.id == id) {
+                return Ok(id);
+            }
+
+            match self.record(id) {
+                Some(Record {
+                    replaced_by: Some(replacement),
+                    ..
+                }) => id = replacement,
+                Some(record) if record.status.is_finished() => return Err(record.status),
+                _ => return Err(Status::Done),
+            }
+        }
+    }
+
+    /// Point any jobs waiting on a job that was replaced at its replacement instead
+    fn replace_dependency(&self, pending: &mut VecDeque<Queued>, from: u64, to: u64) {
+        for queued in pending.iter_mut() {
+            if let Some(dependency) = queued.depends_on.iter_mut().find(|d| **d == from) {
+                *dependency = to;
+
+                let depends_on = queued.depends_on.clone();
+                self.update(queued.id, |record| record.depends_on = depends_on);
+            }
+        }
+    }
+
+    /// Modify a job's persisted record
+    fn update<F: FnOnce(&mut Record)>(&self, id: u64, f: F) {
+        let store = match self.store.get() {
+            Some(store) => store,
+            None => return,
+        };
+
+        match self.record(id) {
+            Some(mut record) => {
+                f(&mut record);
+                save(store, &record);
+            }
+            None => warn!(%id, "missing job record"),
+        }
+    }
+}
+
+impl Record {
+    /// Create a record for a newly queued job
+    fn new(id: u64, job: &dyn Job, depends_on: Vec<u64>) -> Record {
+        Record {
+            id,
+            name: job.name().to_owned(),
+            service: job.service().map(|s| s.proper.clone()),
+            status: Status::Pending,
+            parent: current(),
+            depends_on,
+            worker: None,
+            job: job.persist().unwrap_or_default(),
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+            replaced_by: None,
+            retries: 0,
+            retry_at: None,
+        }
+    }
+}
+
+/// Write a record to the store. Failing to persist a job shouldn't stop it from running, so
+/// any errors are only logged.
+fn save(store: &Tree, record: &Record) {
+    let result = serde_json::to_vec(record)
+        .map_err(|e| e.to_string())
+        .and_then(|value| {
+            store
+                .insert(key(record.id), value)
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        warn!(id = record.id, error = %e, "failed to persist job record");
+    }
+}
+
+/// IDs are stored big-endian so the records are kept in the order they were queued
+fn key(id: u64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+/// Convert a big-endian key into its ID
+fn id_from_key(key: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&key[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelError, JobQueue, Record, Status};
+    use crate::{
+        processor::jobs::{scope, Attempt, Error, Job, ScheduledRun},
+        service::ServiceName,
+    };
+    use async_trait::async_trait;
+    use chrono::Duration;
+    use serde::Serialize;
+    use std::{any::Any, time::Duration as StdDuration};
+
+    #[derive(Serialize)]
+    struct Test {
+        service: Option<ServiceName>,
+        version: u32,
+        mergeable: bool,
+    }
+
+    impl Test {
+        fn boxed(service: Option<&str>, version: u32) -> Box<dyn Job> {
+            Box::new(Test {
+                service: service.map(ServiceName::from),
+                version,
+                mergeable: true,
+            })
+        }
+
+        fn unmergeable(service: Option<&str>, version: u32) -> Box<dyn Job> {
+            Box::new(Test {
+                service: service.map(ServiceName::from),
+                version,
+                mergeable: false,
+            })
+        }
+
+        fn version(job: &dyn Job) -> u32 {
+            (job as &dyn Any).downcast_ref::<Test>().unwrap().version
+        }
+    }
+
+    #[async_trait]
+    impl Job for Test {
+        async fn run(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn name<'a>(&self) -> &'a str {
+            "test"
+        }
+
+        fn service(&self) -> Option<&ServiceName> {
+            self.service.as_ref()
+        }
+
+        fn merge(&mut self, queued: &mut dyn Job) -> bool {
+            self.mergeable && (queued as &mut dyn Any).is::<Test>()
+        }
+    }
+
+    /// A job that runs once its dependencies finish, whether or not they succeeded
+    #[derive(Serialize)]
+    struct Report;
+
+    #[async_trait]
+    impl Job for Report {
+        async fn run(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn name<'a>(&self) -> &'a str {
+            "report"
+        }
+
+        fn requires_dependencies(&self) -> bool {
+            false
+        }
+    }
+
+    fn store() -> sled::Tree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.open_tree("jobs").unwrap()
+    }
+
+    fn record(store: &sled::Tree, id: u64) -> Option<Record> {
+        let raw = store.get(id.to_be_bytes()).unwrap()?;
+        Some(serde_json::from_slice(&raw).unwrap())
+    }
+
+    #[tokio::test]
+    async fn coalesce_queued() {
+        let queue = JobQueue::new();
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 1));
+        queue.push(Test::boxed(Some("wafflehacks/auth"), 2));
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 3));
+
+        // The newest job keeps the position of the one it replaced
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 3);
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 2);
+        assert!(queue.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn serialize_per_service() {
+        let queue = JobQueue::new();
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 1));
+
+        let running = queue.pop(0).await;
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 2));
+        queue.push(Test::boxed(Some("wafflehacks/auth"), 3));
+        queue.push(Test::boxed(None, 4));
+
+        // Other services and jobs without a service are not blocked
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 3);
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 4);
+        assert!(queue.try_pop(0).is_none());
+
+        queue.finish(&running, &Ok(()));
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 2);
+    }
+
+    #[tokio::test]
+    async fn busy_services() {
+        let queue = JobQueue::new();
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 1));
+        queue.push(Test::boxed(Some("wafflehacks/auth"), 2));
+        queue.push(Test::boxed(None, 3));
+
+        let running = queue.pop(0).await;
+        let busy = queue.busy();
+        assert_eq!(busy.len(), 2);
+        assert!(busy.contains("wafflehacks/cms"));
+        assert!(busy.contains("wafflehacks/auth"));
+
+        queue.finish(&running, &Ok(()));
+        assert!(!queue.busy().contains("wafflehacks/cms"));
+    }
+
+    #[tokio::test]
+    async fn persist_lifecycle() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let first = queue.push(Test::boxed(None, 1));
+        let second = queue.push(Test::boxed(None, 2));
+        assert_eq!(record(&store, first).unwrap().status, Status::Pending);
+        assert_eq!(record(&store, first).unwrap().job["version"], 1);
+
+        let queued = queue.pop(3).await;
+        assert_eq!(record(&store, first).unwrap().status, Status::Running);
+        assert_eq!(record(&store, first).unwrap().worker, Some(3));
+
+        queue.finish(&queued, &Err(Error::new("exploded")));
+        let finished = record(&store, first).unwrap();
+        assert_eq!(finished.status, Status::Failed);
+        assert_eq!(finished.error.as_deref(), Some("exploded"));
+        assert!(finished.finished_at.is_some());
+
+        // Only finished jobs are pruned
+        assert_eq!(queue.prune(Duration::zero()).unwrap(), 1);
+        assert!(record(&store, first).is_none());
+        assert_eq!(record(&store, second).unwrap().status, Status::Pending);
+    }
+
+    #[tokio::test]
+    async fn cancel_pending() {
+        let queue = JobQueue::new();
+        queue.restore(store()).unwrap();
+
+        let running = queue.push(Test::boxed(None, 1));
+        queue.pop(0).await;
+        let pending = queue.push(Test::boxed(None, 2));
+
+        assert_eq!(queue.cancel(pending).unwrap().status, Status::Cancelled);
+        assert!(queue.try_pop(0).is_none());
+        assert!(matches!(
+            queue.cancel(running),
+            Err(CancelError::NotPending(Status::Running))
+        ));
+        assert!(matches!(queue.cancel(42), Err(CancelError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn retry_with_delay() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let failed = queue.push(Test::boxed(Some("wafflehacks/cms"), 1));
+        let running = queue.pop(0).await;
+        queue.push(Test::unmergeable(Some("wafflehacks/cms"), 2));
+        queue.push(Test::boxed(Some("wafflehacks/auth"), 3));
+
+        queue.retry(
+            running,
+            &Error::new("timed out"),
+            StdDuration::from_millis(50),
+        );
+        let retrying = record(&store, failed).unwrap();
+        assert_eq!(retrying.status, Status::Pending);
+        assert_eq!(retrying.retries, 1);
+        assert_eq!(retrying.error.as_deref(), Some("timed out"));
+        assert!(retrying.retry_at.is_some());
+
+        // Newer jobs for the same service wait for the retry
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 3);
+        assert!(queue.try_pop(0).is_none());
+
+        let retried = queue.pop(0).await;
+        assert_eq!(Test::version(retried.job.as_ref()), 1);
+        assert_eq!(retried.retries, 1);
+        assert!(queue.try_pop(0).is_none());
+
+        queue.finish(&retried, &Ok(()));
+        assert_eq!(record(&store, failed).unwrap().status, Status::Done);
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_replaced() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let failed = queue.push(Test::boxed(Some("wafflehacks/cms"), 1));
+        let running = queue.pop(0).await;
+        let newer = queue.push(Test::boxed(Some("wafflehacks/cms"), 2));
+
+        // The newer job already covers the failed one, so it doesn't need to run again
+        queue.retry(
+            running,
+            &Error::new("timed out"),
+            StdDuration::from_secs(60),
+        );
+        let replaced = record(&store, failed).unwrap();
+        assert_eq!(replaced.status, Status::Cancelled);
+        assert_eq!(
+            replaced.error,
+            Some(format!("timed out, replaced by job {}", newer))
+        );
+
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 2);
+        assert!(queue.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn record_parent() {
+        let queue = JobQueue::new();
+        queue.restore(store()).unwrap();
+
+        let parent = queue.push(Test::boxed(None, 1));
+        let attempt = Attempt {
+            id: parent,
+            last: true,
+        };
+        let child = scope(Some(attempt), async { queue.push(Test::boxed(None, 2)) }).await;
+
+        let records = queue.records().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].parent, None);
+        assert_eq!(records[1].id, child);
+        assert_eq!(records[1].parent, Some(parent));
+    }
+
+    #[tokio::test]
+    async fn restore_interrupted() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+        queue.push(Test::boxed(None, 1));
+        queue.push(Test::boxed(None, 2));
+        let done = queue.pop(0).await;
+        queue.finish(&done, &Ok(()));
+        queue.pop(0).await;
+
+        // Test jobs can't be recreated, so the interrupted job is marked as failed
+        let restarted = JobQueue::new();
+        assert_eq!(restarted.restore(store.clone()).unwrap(), 0);
+        assert_eq!(record(&store, 0).unwrap().status, Status::Done);
+        assert_eq!(record(&store, 1).unwrap().status, Status::Failed);
+
+        // New jobs continue on from the existing IDs
+        assert_eq!(restarted.push(Test::boxed(None, 3)), 2);
+    }
+
+    #[tokio::test]
+    async fn dependencies_run_in_order() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let first = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let second = queue.push_after(Test::boxed(Some("wafflehacks/auth"), 2), vec![first]);
+        queue.push(Test::unmergeable(Some("wafflehacks/auth"), 3));
+        queue.push(Test::boxed(Some("wafflehacks/cms"), 4));
+        assert_eq!(record(&store, second).unwrap().depends_on, vec![first]);
+
+        // Newer jobs for the same service wait behind the blocked job
+        let running = queue.pop(0).await;
+        assert_eq!(Test::version(running.job.as_ref()), 1);
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 4);
+        assert!(queue.try_pop(0).is_none());
+
+        queue.finish(&running, &Ok(()));
+        assert!(record(&store, second).unwrap().depends_on.is_empty());
+        assert_eq!(Test::version(queue.pop(0).await.job.as_ref()), 2);
+    }
+
+    #[tokio::test]
+    async fn skip_failed_dependencies() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let first = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let second = queue.push_after(Test::boxed(Some("wafflehacks/auth"), 2), vec![first]);
+        let third = queue.push_after(Test::boxed(Some("wafflehacks/cms"), 3), vec![second]);
+        let report = queue.push_after(Box::new(Report), vec![first, second, third]);
+
+        let running = queue.pop(0).await;
+        queue.finish(&running, &Err(Error::new("exploded")));
+
+        // Everything that transitively requires the failed job is skipped
+        for id in [second, third] {
+            let skipped = record(&store, id).unwrap();
+            assert_eq!(skipped.status, Status::Skipped);
+            assert!(skipped.finished_at.is_some());
+        }
+
+        let remaining = queue.pop(0).await;
+        assert_eq!(remaining.id, report);
+        assert!(record(&store, report).unwrap().depends_on.is_empty());
+        assert!(queue.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn cancel_skips_dependents() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let first = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let second = queue.push_after(Test::boxed(Some("wafflehacks/auth"), 2), vec![first]);
+
+        queue.cancel(first).unwrap();
+        assert_eq!(record(&store, second).unwrap().status, Status::Skipped);
+        assert!(queue.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn coalesce_dependencies() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let first = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let replaced = queue.push_after(Test::boxed(Some("wafflehacks/auth"), 2), vec![first]);
+        let report = queue.push_after(Box::new(Report), vec![replaced]);
+        let newer = queue.push(Test::boxed(Some("wafflehacks/auth"), 3));
+
+        // The newer job inherits the dependencies and dependents of the one it replaced
+        assert_eq!(record(&store, replaced).unwrap().replaced_by, Some(newer));
+        assert_eq!(record(&store, newer).unwrap().depends_on, vec![first]);
+        assert_eq!(record(&store, report).unwrap().depends_on, vec![newer]);
+
+        let running = queue.pop(0).await;
+        assert_eq!(running.id, first);
+        assert!(queue.try_pop(0).is_none());
+
+        queue.finish(&running, &Ok(()));
+        let running = queue.pop(0).await;
+        assert_eq!(running.id, newer);
+        assert!(queue.try_pop(0).is_none());
+
+        queue.finish(&running, &Ok(()));
+        assert_eq!(queue.pop(0).await.id, report);
+    }
+
+    #[tokio::test]
+    async fn restore_dependencies() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let done = queue.push(Test::boxed(None, 1));
+        let interrupted = queue.push(Test::boxed(None, 2));
+        let running = queue.pop(0).await;
+        queue.finish(&running, &Ok(()));
+        queue.pop(0).await;
+
+        let now = chrono::Utc::now();
+        let ready = queue.push_after(
+            Box::new(ScheduledRun::new("wafflehacks/cms".into(), now)),
+            vec![done],
+        );
+        let blocked = queue.push_after(
+            Box::new(ScheduledRun::new("wafflehacks/auth".into(), now)),
+            vec![interrupted],
+        );
+
+        // The interrupted job can't be restored, so the job that requires it is skipped
+        let restarted = JobQueue::new();
+        assert_eq!(restarted.restore(store.clone()).unwrap(), 2);
+        assert_eq!(record(&store, interrupted).unwrap().status, Status::Failed);
+        assert_eq!(record(&store, blocked).unwrap().status, Status::Skipped);
+
+        assert_eq!(restarted.pop(0).await.id, ready);
+        assert!(restarted.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn finished_dependencies() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let succeeded = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let running = queue.pop(0).await;
+        queue.finish(&running, &Ok(()));
+
+        let failed = queue.push(Test::boxed(Some("wafflehacks/auth"), 2));
+        let running = queue.pop(0).await;
+        queue.finish(&running, &Err(Error::new("exploded")));
+
+        // Dependencies that finished before the job was queued don't hold it back
+        let ready = queue.push_after(Test::boxed(Some("wafflehacks/cms"), 3), vec![succeeded]);
+        assert!(record(&store, ready).unwrap().depends_on.is_empty());
+        assert_eq!(queue.pop(0).await.id, ready);
+
+        let skipped = queue.push_after(Test::boxed(Some("wafflehacks/api"), 4), vec![failed]);
+        assert_eq!(record(&store, skipped).unwrap().status, Status::Skipped);
+        assert!(queue.try_pop(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn unfinished_jobs() {
+        let store = store();
+        let queue = JobQueue::new();
+        queue.restore(store.clone()).unwrap();
+
+        let done = queue.push(Test::boxed(Some("wafflehacks/proxy"), 1));
+        let running = queue.pop(0).await;
+        queue.finish(&running, &Ok(()));
+
+        let active = queue.push(Test::boxed(Some("wafflehacks/auth"), 2));
+        queue.pop(0).await;
+        let pending = queue.push(Test::boxed(Some("wafflehacks/auth"), 3));
+
+        let mut ids = queue
+            .unfinished()
+            .into_iter()
+            .map(|record| record.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![active, pending]);
+        assert!(!ids.contains(&done));
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Error, Job, Status};
+use crate::notifier::{self, Event, State};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReportDeployment {
+    after: String,
+    /// The job updating each service in the deployment
+    updates: Vec<(String, u64)>,
+    /// The configurations that could not be parsed
+    parse_failures: Vec<String>,
+}
+
+impl ReportDeployment {
+    /// Create a new report deployment job, it should depend on all of the updates
+    pub fn new<S: Into<String>>(
+        after: S,
+        updates: Vec<(String, u64)>,
+        parse_failures: Vec<String>,
+    ) -> Self {
+        Self {
+            after: after.into(),
+            updates,
+            parse_failures,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for ReportDeployment {
+    #[instrument(skip(self), fields(after = %&self.after[..8], name = %self.name()))]
+    async fn run(&self) -> Result<(), Error> {
+        let queue = super::instance();
+
+        // Updates that are replaced are reported by whatever took over from them
+        let mut skipped = Vec::new();
+        for (name, id) in &self.updates {
+            let mut record = queue.record(*id);
+            while let Some(replaced_by) = record.as_ref().and_then(|r| r.replaced_by) {
+                record = queue.record(replaced_by);
+            }
+
+            if matches!(record, Some(r) if r.status == Status::Skipped) {
+                skipped.push(name.as_str());
+            }
+        }
+        skipped.sort_unstable();
+
+        let mut failures = Vec::new();
+        if !self.parse_failures.is_empty() {
+            failures.push(format!(
+                "unable to parse: {}",
+                self.parse_failures.join(", ")
+            ));
+        }
+        if !skipped.is_empty() {
+            warn!(services = ?skipped, "skipped updates due to failed dependencies");
+            failures.push(format!(
+                "skipped due to failed dependencies: {}",
+                skipped.join(", ")
+            ));
+        }
+
+        let state = if failures.is_empty() {
+            info!("deployment finished");
+            State::Success
+        } else {
+            State::Failure(failures.join("; "))
+        };
+        notifier::notify(Event::deployment(&self.after, state)).await;
+
+        Ok(())
+    }
+
+    fn name<'a>(&self) -> &'a str {
+        "report_deployment"
+    }
+
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,119 @@
+use super::{
+    update_service::{load_environment, revoke, run_to_completion, JOB_LOG_LINES},
+    Error, Job,
+};
+use crate::{
+    deployer::{self, CreateOpts, HistoryEntry, Outcome},
+    fail_notify, git,
+    notifier::{self, Event},
+    service::{registry::REGISTRY, ServiceName},
+    vault,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScheduledRun {
+    name: ServiceName,
+    at: DateTime<Utc>,
+}
+
+impl ScheduledRun {
+    /// Create a new scheduled run job for the firing at the given time
+    pub fn new(name: ServiceName, at: DateTime<Utc>) -> Self {
+        Self { name, at }
+    }
+
+    /// When the schedule fired
+    pub fn at(&self) -> DateTime<Utc> {
+        self.at
+    }
+}
+
+#[async_trait]
+impl Job for ScheduledRun {
+    #[instrument(skip(self), fields(name = %self.name, at = %self.at))]
+    async fn run(&self) -> Result<(), Error> {
+        macro_rules! fail {
+            ($result:expr) => {
+                fail_notify!(scheduled_run, &self.name; $result; "an error occurred while running scheduled job")
+            };
+        }
+
+        // Use the latest configuration in case it changed since the run was scheduled
+        let service = match REGISTRY.read().await.get(&self.name.proper).cloned() {
+            Some(service) => service,
+            None => {
+                info!("service was removed before it could run, skipping");
+                return Ok(());
+            }
+        };
+        let commit = fail!(git::instance().head().await);
+
+        let mut options = CreateOpts::builder()
+            .name(&*self.name)
+            .image(&service.docker.image, &service.docker.tag)
+            .resources(service.resources.clone())
+            .commit(&commit);
+
+        for volume in &service.volumes {
+            options = options.volume(volume.clone());
+        }
+
+        // Every run gets its own credentials which only live as long as the run
+        let (options, leases, static_secrets) =
+            fail!(load_environment(&self.name, &service, options).await);
+
+        let id = match deployer::instance().create_one_off(options.build()).await {
+            Ok(id) => id,
+            Err(e) => {
+                revoke(&leases).await;
+                fail!(Err(e))
+            }
+        };
+        vault::instance().register_leases(&id, leases).await;
+
+        let result = run_to_completion(&id, JOB_LOG_LINES).await;
+        let revoked = vault::instance().revoke_leases(&id).await;
+        fail!(deployer::instance().delete(&id).await);
+        fail!(revoked);
+        fail!(
+            vault::instance()
+                .put_static(&self.name, static_secrets)
+                .await
+        );
+
+        let (code, logs) = fail!(result);
+        info!(%code, "scheduled run exited");
+
+        // Runs are recorded at the time they were scheduled so missed runs can be found later
+        let outcome = match code {
+            0 => Outcome::Success,
+            _ => Outcome::Failure,
+        };
+        let entry = HistoryEntry {
+            timestamp: self.at.timestamp() as u64,
+            ..HistoryEntry::new(commit, service, vec![id], outcome)
+        };
+        if let Err(e) = deployer::instance().record(&self.name, entry).await {
+            warn!(error = %e, "failed to record run in history");
+        }
+
+        notifier::notify(Event::scheduled_run_exited(&self.name, code, &logs)).await;
+
+        match code {
+            0 => Ok(()),
+            _ => Err(Error::new(format!("exited with status code {}", code))),
+        }
+    }
+
+    fn name<'a>(&self) -> &'a str {
+        "scheduled_run"
+    }
+
+    fn service(&self) -> Option<&ServiceName> {
+        Some(&self.name)
+    }
+}
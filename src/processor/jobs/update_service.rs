@@ -1,34 +1,72 @@
-use super::Job;
+use super::{Error, Job, Retryable};
 use crate::{
     config,
-    deployer::{self, CreateOpts},
-    dns, fail_notify,
+    deployer::{self, CreateOpts, CreateOptsBuilder, HistoryEntry, LogOpts, Outcome},
+    dns, fail_notify, git,
     notifier::{self, Event, State},
-    service::{registry::REGISTRY, AWSPart, Format, Secret, Service, ServiceName},
-    vault::{self, Aws},
+    service::{
+        persisted,
+        registry::REGISTRY,
+        template::{self, Error as TemplateError, InvalidVariable, Variables},
+        AWSPart, Format, Health, Hook, Kind, Secret, Service, ServiceName, Strategy,
+    },
+    vault::{self, Aws, Lease},
 };
 use async_trait::async_trait;
+use futures::TryStreamExt;
 use rand::{distributions::Alphanumeric, Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use std::{any::Any, collections::HashMap, time::Duration};
+use tokio::time;
 use tracing::{debug, error, info, instrument, warn};
 
-#[derive(Debug)]
+/// The number of lines from the end of a job's logs to report
+pub(super) const JOB_LOG_LINES: u64 = 50;
+
+/// The number of lines from the end of a failed hook's logs to report
+const HOOK_LOG_LINES: u64 = 10;
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateService {
+    #[serde(deserialize_with = "persisted::deserialize")]
     config: Service,
     name: ServiceName,
+    commit: Option<String>,
 }
 
 impl UpdateService {
     /// Create a new update service job
     pub fn new(config: Service, name: ServiceName) -> Self {
-        Self { config, name }
+        Self {
+            config,
+            name,
+            commit: None,
+        }
+    }
+
+    /// Create a new update service job for a configuration from a specific commit
+    pub fn with_commit<S: Into<String>>(config: Service, name: ServiceName, commit: S) -> Self {
+        Self {
+            config,
+            name,
+            commit: Some(commit.into()),
+        }
+    }
+
+    /// Add the deployment to the service's history
+    async fn record(&self, commit: &str, ids: &[String], outcome: Outcome) {
+        let entry = HistoryEntry::new(commit, self.config.clone(), ids.to_vec(), outcome);
+        if let Err(e) = deployer::instance().record(&self.name, entry).await {
+            warn!(error = %e, "failed to record deployment in history");
+        }
     }
 }
 
 #[async_trait]
 impl Job for UpdateService {
     #[instrument(skip(self), fields(name = %self.name))]
-    async fn run(&self) {
+    async fn run(&self) -> Result<(), Error> {
         macro_rules! fail {
             ($result:expr) => {
                 fail_notify!(service_update, &self.name; $result; "an error occurred while updating service")
@@ -38,171 +76,230 @@ impl Job for UpdateService {
         let config = config::instance();
         let service = &self.config;
 
+        // Configurations without an explicit commit come from the current state of the repository
+        let commit = match &self.commit {
+            Some(commit) => commit.clone(),
+            None => fail!(git::instance().head().await),
+        };
+
         notifier::notify(Event::service_update(&self.name, State::InProgress)).await;
 
-        // Update the service in the registry
-        let mut reg = REGISTRY.write().await;
-        reg.insert(self.name.clone(), service.clone());
+        // Update the service in the registry, the queue ensures only this job is modifying
+        // the service so the registry doesn't need to stay locked
+        REGISTRY
+            .write()
+            .await
+            .insert(self.name.proper.clone(), service.clone());
+
+        // Scheduled jobs are run by the scheduler instead of when they are deployed
+        if service.kind == Kind::Job && service.schedule.is_some() {
+            info!("registered scheduled job");
+            notifier::notify(Event::service_update(&self.name, State::Success)).await;
+            return Ok(());
+        }
 
         // Create the base container creation args
         let mut options = CreateOpts::builder()
             .name(&*self.name)
-            .image(&service.docker.image, &service.docker.tag);
+            .image(&service.docker.image, &service.docker.tag)
+            .resources(service.resources.clone())
+            .commit(&commit);
 
-        if service.web.enabled {
-            let domain = match service.web.domain.clone() {
-                Some(d) => d,
-                None => format!("{}.{}", &self.name.domain, &config.deployment.domain),
-            };
-
-            options = options.routing(domain, service.web.path.as_deref());
+        for volume in &service.volumes {
+            options = options.volume(volume.clone());
         }
 
-        for (k, v) in service.environment.iter() {
-            options = options.environment(k.to_uppercase(), v);
-            debug!(name = %k, "added static environment variable");
-        }
-        info!("loaded static environment variables");
-
-        // Get existing secrets
-        let mut static_secrets = match fail!(vault::instance().fetch_static(&self.name).await) {
-            Some(s) => s,
-            None => Default::default(),
-        };
-
-        // Load secrets into the environment
-        let mut leases = Vec::new();
-        let mut aws_creds: Option<Aws> = None;
-        for (k, secret) in service.secrets.iter() {
-            let value = match secret {
-                Secret::Aws { role, part } => {
-                    // Retrieve the initial set of credentials if they haven't been already
-                    if aws_creds.is_none() {
-                        let (creds, lease) = fail!(vault::instance().aws_credentials(role).await);
-                        aws_creds = Some(creds);
-                        leases.push(lease);
-                    }
+        let (mut options, leases, static_secrets) =
+            fail!(load_environment(&self.name, service, options).await);
 
-                    match part {
-                        AWSPart::Access => aws_creds.as_ref().unwrap().access_key.clone(),
-                        AWSPart::Secret => aws_creds.as_ref().unwrap().secret_key.clone(),
+        // The credentials belong to the container their leases are registered with, until then
+        // they have to be revoked if the update fails
+        macro_rules! fail_revoke {
+            ($result:expr) => {
+                match $result {
+                    Ok(v) => v,
+                    Err(e) => {
+                        revoke(&leases).await;
+                        fail!(Err(e))
                     }
                 }
-                Secret::Generate {
-                    format,
-                    length,
-                    regenerate,
-                } => {
-                    let value = if *regenerate {
-                        generate_value(format, *length)
-                    } else {
-                        static_secrets
-                            .get(k)
-                            .cloned()
-                            .unwrap_or_else(|| generate_value(format, *length))
-                    };
-
-                    static_secrets.insert(k.clone(), value.clone());
-                    value
-                }
-                Secret::Load => static_secrets.get(k).cloned().unwrap_or_else(|| {
-                    warn!(key = %k, "failed to load secret from Vault");
-                    String::new()
-                }),
             };
-
-            options = options.environment(k.to_uppercase(), value);
-            debug!(name = %k, r#type = %secret.name(), "added secret from vault");
         }
-        info!("loaded secrets from vault into environment");
 
-        if let Some(postgres) = service.dependencies.postgres(&self.name.sanitized) {
-            // Create the role if it doesn't exist
-            let roles = fail!(vault::instance().list_database_roles().await);
-            if !roles.contains(&postgres.role.to_owned()) {
-                fail!(vault::instance().create_database_role(postgres.role).await);
+        if service.kind == Kind::Job {
+            // Remove any containers left over from before the service became a job
+            for id in fail_revoke!(deployer::instance().service_ids(&self.name).await) {
+                fail_revoke!(deployer::instance().stop(&id).await);
+                fail_revoke!(deployer::instance().delete(&id).await);
+                fail_revoke!(vault::instance().revoke_leases(&id).await);
             }
 
-            let (credentials, lease) = fail!(
+            // Jobs only ever have a single container which gets cleaned up once it exits
+            let id = fail_revoke!(deployer::instance().create_one_off(options.build()).await);
+            vault::instance().register_leases(&id, leases).await;
+
+            let result = run_to_completion(&id, JOB_LOG_LINES).await;
+            let revoked = vault::instance().revoke_leases(&id).await;
+            fail!(deployer::instance().delete(&id).await);
+            fail!(revoked);
+            fail!(
                 vault::instance()
-                    .get_database_credentials(postgres.role)
+                    .put_static(&self.name, static_secrets)
                     .await
             );
-            leases.push(lease);
-            let connection_url = &config
-                .dependencies
-                .postgres
-                .replace("{{username}}", &credentials.username)
-                .replace("{{password}}", &credentials.password)
-                .replace("{{database}}", postgres.role);
-
-            options = options.environment(postgres.name.to_uppercase(), connection_url);
-            debug!(name = %postgres.name, "added postgres database url");
+
+            let (code, logs) = fail!(result);
+            info!(%code, "job exited");
+
+            let outcome = match code {
+                0 => Outcome::Success,
+                _ => Outcome::Failure,
+            };
+            self.record(&commit, &[id], outcome).await;
+            notifier::notify(Event::job_run(&self.name, code, &logs)).await;
+            return match code {
+                0 => Ok(()),
+                _ => Err(Error::new(format!("job exited with status code {}", code))),
+            };
+        }
+
+        // Run the hook from the new version before anything is replaced, it gets the same
+        // environment but none of the routing or health checks
+        if let Some(hook) = &service.hooks.pre_deploy {
+            if let Err(e) = run_hook(options.clone(), hook).await {
+                error!(error = %e, "pre-deploy hook failed, keeping the previous version");
+                revoke(&leases).await;
+                self.record(&commit, &[], Outcome::Failure).await;
+
+                let state = State::Failure(e.to_string());
+                notifier::notify(Event::service_update(&self.name, state)).await;
+                return Err(Error::new(e.to_string()));
+            }
+            info!("pre-deploy hook succeeded");
+        }
+
+        if let Some(health) = &service.health {
+            options = options.health(health.clone());
         }
-        if let Some(name) = service.dependencies.redis() {
-            options = options.environment(name.to_uppercase(), &config.dependencies.redis);
-            debug!(name = %name, "added redis url");
+
+        if service.web.enabled {
+            let domain = match service.web.domain.clone() {
+                Some(d) => d,
+                None => format!("{}.{}", &self.name.domain, &config.deployment.domain),
+            };
+
+            options = options.routing(domain, service.web.path.as_deref());
         }
-        info!("loaded service dependencies into environment");
 
-        let known_services = fail!(deployer::instance().list().await);
-        let previous_id = known_services.get(&*self.name);
+        let previous_ids = fail_revoke!(deployer::instance().service_ids(&self.name).await);
+        let recreate = service.strategy == Strategy::Recreate;
 
         // Perform a rolling update of the service (if a previous version existed)
         // Flow (assuming previous version existed):
-        //   - create new version
-        //   - stop old version
-        //   - start new version
+        //   - create new version's replicas
+        //   - stop old version's replicas (recreate only)
+        //   - start new version's replicas
+        //   - wait for new version's replicas to become healthy (if configured)
         //   - on:
         //     - failure:
-        //       - start old version
-        //       - delete new version
+        //       - stop new version's replicas
+        //       - start old version's replicas (recreate only)
+        //       - delete new version's replicas
         //     - success:
-        //       - delete old version
+        //       - point the internal DNS at the new version's replicas
+        //       - stop old version's replicas (blue-green only)
+        //       - delete old version's replicas
         // Flow (new service):
-        //   - create new version
-        //   - start new version
-        let new_id = fail!(deployer::instance().create(options.build()).await);
-        if let Some(id) = previous_id {
-            fail!(deployer::instance().stop(id).await);
+        //   - create new version's replicas
+        //   - start new version's replicas
+        //   - wait for new version's replicas to become healthy (if configured)
+        //   - on failure:
+        //     - stop new version's replicas
+        //     - delete new version's replicas
+        let new_ids = fail_revoke!(
+            deployer::instance()
+                .create(options.replicas(service.replicas).build())
+                .await
+        );
+
+        // Save the credential leases for renewal, all the replicas share the same credentials so
+        // they are tracked with the first replica
+        vault::instance().register_leases(&new_ids[0], leases).await;
+        if recreate {
+            for id in &previous_ids {
+                fail!(deployer::instance().stop(id).await);
+            }
+        }
+        let deployed = start_replicas(&new_ids, service.health.as_ref()).await;
+
+        // Switch the internal DNS record(s) over before the old version goes away
+        if deployed.is_ok() {
+            let mut ips = Vec::new();
+            for id in &new_ids {
+                ips.push(fail!(deployer::instance().ip(id).await));
+            }
+            fail!(dns::instance().register(&self.name.domain, &ips).await);
         }
-        let deployed = deployer::instance().start(&new_id).await;
-        match (previous_id, deployed) {
+
+        match (previous_ids.is_empty(), deployed) {
             // existing deployment succeeded, cleanup old version
-            (Some(id), Ok(_)) => {
-                fail!(deployer::instance().delete(id).await);
-                fail!(vault::instance().revoke_leases(id).await);
+            (false, Ok(_)) => {
+                for id in &previous_ids {
+                    if !recreate {
+                        fail!(deployer::instance().stop(id).await);
+                    }
+                    fail!(deployer::instance().delete(id).await);
+                    fail!(vault::instance().revoke_leases(id).await);
+                }
             }
             // existing deployment failed, start old version and cleanup
-            (Some(id), Err(e)) => {
-                error!(error = %e, "failed to deploy new service, restarting old version");
-                fail!(deployer::instance().start(id).await);
-                fail!(deployer::instance().delete(&new_id).await);
-                fail!(vault::instance().revoke_leases(&new_id).await);
-                return;
+            (false, Err(e)) => {
+                error!(error = %e, "failed to deploy new service, restoring old version");
+                for id in &new_ids {
+                    fail!(deployer::instance().stop(id).await);
+                }
+                if recreate {
+                    for id in &previous_ids {
+                        fail!(deployer::instance().start(id).await);
+                    }
+                }
+                for id in &new_ids {
+                    fail!(deployer::instance().delete(id).await);
+                    fail!(vault::instance().revoke_leases(id).await);
+                }
+
+                self.record(&commit, &new_ids, Outcome::Failure).await;
+
+                let state = State::Failure(e.to_string());
+                notifier::notify(Event::service_update(&self.name, state)).await;
+                return Err(Error::new(e.to_string()));
             }
-            // previously non-existent deployment failed, nothing to do
-            (None, Err(e)) => {
+            // previously non-existent deployment failed, cleanup new version
+            (true, Err(e)) => {
                 error!(error = %e, "failed to deploy new service");
-                return;
+                for id in &new_ids {
+                    fail!(deployer::instance().stop(id).await);
+                    fail!(deployer::instance().delete(id).await);
+                    fail!(vault::instance().revoke_leases(id).await);
+                }
+
+                self.record(&commit, &new_ids, Outcome::Failure).await;
+
+                let state = State::Failure(e.to_string());
+                notifier::notify(Event::service_update(&self.name, state)).await;
+                return Err(Error::new(e.to_string()));
             }
             // previously non-existent deployment succeeded, nothing to do
-            (None, Ok(_)) => {}
+            (true, Ok(_)) => {}
         }
 
-        // Save the credential leases for renewal
-        vault::instance().register_leases(&new_id, leases).await;
-
         // Revoke old leases
-        if let Some(old_id) = previous_id {
+        for old_id in &previous_ids {
             fail!(vault::instance().revoke_leases(old_id).await);
         }
 
-        // Register the internal DNS record(s)
-        let ip = fail!(deployer::instance().ip(&new_id).await);
-        fail!(dns::instance().register(&self.name.domain, &ip).await);
-
-        info!("deployed with id \"{}\"", new_id);
+        info!("deployed with ids \"{}\"", new_ids.join(", "));
+        self.record(&commit, &new_ids, Outcome::Success).await;
         notifier::notify(Event::service_update(&self.name, State::Success)).await;
 
         // Save any modifications to the static secrets
@@ -211,11 +308,233 @@ impl Job for UpdateService {
                 .put_static(&self.name, static_secrets)
                 .await
         );
+        Ok(())
     }
 
     fn name<'a>(&self) -> &'a str {
         "update_service"
     }
+
+    fn service(&self) -> Option<&ServiceName> {
+        Some(&self.name)
+    }
+
+    fn merge(&mut self, queued: &mut dyn Job) -> bool {
+        (queued as &mut dyn Any).is::<UpdateService>()
+    }
+}
+
+/// Load a service's static environment variables, secrets, and dependencies into its container
+/// options. The credential leases that were created and the service's static secrets are also
+/// returned so they can be saved once the service is running.
+pub(super) async fn load_environment(
+    name: &ServiceName,
+    service: &Service,
+    mut options: CreateOptsBuilder,
+) -> Result<(CreateOptsBuilder, Vec<Lease>, HashMap<String, String>), EnvironmentError> {
+    let config = config::instance();
+
+    // Get existing secrets
+    let mut static_secrets = vault::instance()
+        .fetch_static(name)
+        .await?
+        .unwrap_or_default();
+
+    // Load the secrets first so they can be referenced from the static environment variables
+    let mut leases = Vec::new();
+    let mut secrets = HashMap::new();
+    let mut aws_creds: Option<Aws> = None;
+    for (k, secret) in service.secrets.iter() {
+        let value = match secret {
+            Secret::Aws { role, part } => {
+                // Retrieve the initial set of credentials if they haven't been already
+                if aws_creds.is_none() {
+                    let (creds, lease) = vault::instance().aws_credentials(role).await?;
+                    aws_creds = Some(creds);
+                    leases.push(lease);
+                }
+
+                match part {
+                    AWSPart::Access => aws_creds.as_ref().unwrap().access_key.clone(),
+                    AWSPart::Secret => aws_creds.as_ref().unwrap().secret_key.clone(),
+                }
+            }
+            Secret::Generate {
+                format,
+                length,
+                regenerate,
+            } => {
+                let value = if *regenerate {
+                    generate_value(format, *length)
+                } else {
+                    static_secrets
+                        .get(k)
+                        .cloned()
+                        .unwrap_or_else(|| generate_value(format, *length))
+                };
+
+                static_secrets.insert(k.clone(), value.clone());
+                value
+            }
+            Secret::Load => static_secrets.get(k).cloned().unwrap_or_else(|| {
+                warn!(key = %k, "failed to load secret from Vault");
+                String::new()
+            }),
+        };
+
+        debug!(name = %k, r#type = %secret.name(), "loaded secret from vault");
+        secrets.insert(k.clone(), value);
+    }
+
+    let variables = Variables::new(
+        name,
+        service,
+        secrets.clone(),
+        &config.deployment.domain,
+        &config.dns.zone,
+    );
+    for (k, v) in variables.environment()? {
+        debug!(name = %k, "added static environment variable");
+        options = options.environment(k, v);
+    }
+    info!("loaded static environment variables");
+
+    for (k, v) in secrets {
+        options = options.environment(k.to_uppercase(), v);
+        debug!(name = %k, "added secret");
+    }
+    info!("loaded secrets from vault into environment");
+
+    if let Some(postgres) = service.dependencies.postgres(&name.sanitized) {
+        // Create the role if it doesn't exist
+        let roles = vault::instance().list_database_roles().await?;
+        if !roles.contains(&postgres.role.to_owned()) {
+            vault::instance()
+                .create_database_role(postgres.role)
+                .await?;
+        }
+
+        let (credentials, lease) = vault::instance()
+            .get_database_credentials(postgres.role)
+            .await?;
+        leases.push(lease);
+        let connection_url =
+            template::render(&config.dependencies.postgres, |reference| match reference {
+                "username" => Ok(credentials.username.clone()),
+                "password" => Ok(credentials.password.clone()),
+                "database" => Ok(postgres.role.to_owned()),
+                _ => Err(TemplateError::UnknownReference(reference.to_owned())),
+            })
+            .map_err(EnvironmentError::Postgres)?;
+
+        options = options.environment(postgres.name.to_uppercase(), connection_url);
+        debug!(name = %postgres.name, "added postgres database url");
+    }
+    if let Some(variable) = service.dependencies.redis() {
+        options = options.environment(variable.to_uppercase(), &config.dependencies.redis);
+        debug!(name = %variable, "added redis url");
+    }
+    info!("loaded service dependencies into environment");
+
+    Ok((options, leases, static_secrets))
+}
+
+/// Why a service's environment could not be loaded
+#[derive(Debug, thiserror::Error)]
+pub(super) enum EnvironmentError {
+    #[error(transparent)]
+    Vault(#[from] vault::Error),
+    #[error(transparent)]
+    Variable(#[from] InvalidVariable),
+    #[error("failed to render postgres connection url: {0}")]
+    Postgres(#[source] TemplateError),
+}
+
+impl Retryable for EnvironmentError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            EnvironmentError::Vault(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+/// Start each of a service's replicas, waiting for them to become healthy if a health check
+/// is configured
+async fn start_replicas(ids: &[String], health: Option<&Health>) -> Result<(), deployer::Error> {
+    for id in ids {
+        deployer::instance().start(id).await?;
+    }
+
+    if let Some(health) = health {
+        for id in ids {
+            deployer::wait_until_healthy(id, health).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Why a hook failed to run
+#[derive(Debug, thiserror::Error)]
+enum HookError {
+    #[error("pre-deploy hook exited with status code {code}:\n{logs}")]
+    Exited { code: i64, logs: String },
+    #[error("pre-deploy hook did not finish within {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Deployer(#[from] deployer::Error),
+}
+
+/// Run a hook in a temporary container, waiting for it to exit successfully
+async fn run_hook(options: CreateOptsBuilder, hook: &Hook) -> Result<(), HookError> {
+    let options = options.command(hook.command.clone()).build();
+    let id = deployer::instance().create_one_off(options).await?;
+
+    let result = time::timeout(hook.timeout(), run_to_completion(&id, HOOK_LOG_LINES)).await;
+    // Always try to clean up the container, even if it couldn't be stopped
+    let stopped = deployer::instance().stop(&id).await;
+    let deleted = deployer::instance().delete(&id).await;
+    stopped.and(deleted)?;
+
+    match result {
+        Ok(Ok((0, _))) => Ok(()),
+        Ok(Ok((code, logs))) => Err(HookError::Exited { code, logs }),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err(HookError::Timeout(hook.timeout())),
+    }
+}
+
+/// Revoke the credentials for an update that failed before they were handed to a container
+pub(super) async fn revoke(leases: &[Lease]) {
+    if let Err(e) = vault::instance().revoke(leases).await {
+        error!(error = %e, "failed to revoke credential leases");
+    }
+}
+
+/// Start a job's container and wait for it to exit, returning its exit code and the end of
+/// its logs
+pub(super) async fn run_to_completion(
+    id: &str,
+    lines: u64,
+) -> Result<(i64, String), deployer::Error> {
+    deployer::instance().start(id).await?;
+    let code = deployer::instance().wait(id).await?;
+
+    let options = LogOpts {
+        tail: Some(lines),
+        ..Default::default()
+    };
+    let logs = deployer::instance()
+        .logs(id, options)
+        .await?
+        .try_fold(Vec::new(), |mut logs, chunk| async move {
+            logs.extend_from_slice(&chunk);
+            Ok(logs)
+        })
+        .await?;
+
+    Ok((code, String::from_utf8_lossy(&logs).into_owned()))
 }
 
 /// Generate a given value with the parameters
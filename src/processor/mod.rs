@@ -1,17 +1,72 @@
 use crate::config;
-use tokio::sync::broadcast;
-use tracing::info;
+use futures::future;
+use std::time::Duration;
+use tokio::{sync::broadcast, task::JoinHandle, time};
+use tracing::{error, info, warn};
 
 pub mod jobs;
+pub mod plan;
+mod scheduler;
 mod worker;
 
+/// The running job workers
+pub struct Processor {
+    workers: Vec<JoinHandle<()>>,
+}
+
 /// Create a new job processor
-pub fn spawn(stop: broadcast::Sender<()>) {
+pub fn spawn(stop: broadcast::Sender<()>) -> Processor {
     let cfg = config::instance();
     info!(count = cfg.agent.workers, "spawning job workers");
 
     // Spawn the workers
-    for id in 0..cfg.agent.workers {
-        tokio::spawn(worker::worker(id, stop.subscribe()));
+    let workers = (0..cfg.agent.workers)
+        .map(|id| tokio::spawn(worker::worker(id, stop.subscribe())))
+        .collect();
+
+    tokio::spawn(scheduler::watch(stop.subscribe()));
+    tokio::spawn(jobs::prune(stop.subscribe()));
+
+    Processor { workers }
+}
+
+impl Processor {
+    /// Wait for the workers to finish the jobs they are running once they have been told to
+    /// stop. Any jobs still running after the timeout are abandoned and reported so they can
+    /// be checked on, they will be run again on the next startup.
+    pub async fn shutdown(self, timeout: Duration) {
+        info!(?timeout, "waiting for running jobs to finish");
+        if time::timeout(timeout, future::join_all(self.workers))
+            .await
+            .is_ok()
+        {
+            info!("all running jobs finished");
+            return;
+        }
+
+        let records = match jobs::instance().records() {
+            Ok(records) => records,
+            Err(e) => {
+                error!(error = %e, "failed to find the abandoned jobs");
+                return;
+            }
+        };
+        let abandoned = records
+            .iter()
+            .filter(|r| r.status == jobs::Status::Running)
+            .collect::<Vec<_>>();
+        for record in &abandoned {
+            warn!(
+                id = record.id,
+                name = %record.name,
+                service = ?record.service,
+                started_at = ?record.started_at,
+                "abandoned running job"
+            );
+        }
+        warn!(
+            count = abandoned.len(),
+            "gave up waiting for running jobs, they will be resumed on the next startup"
+        );
     }
 }
@@ -0,0 +1,334 @@
+use crate::{
+    config,
+    git::{self, Action},
+    service::{profile, Kind, Profile, Service, ServiceName},
+};
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, instrument};
+
+/// What deploying the changes between two commits would do
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub services: Vec<Change>,
+    pub errors: Vec<ParseError>,
+}
+
+/// The changes that would be made to a single service
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub name: String,
+    pub action: ChangeAction,
+    /// The configuration fields that changed as dotted paths, only set for updates
+    pub fields: Vec<String>,
+    /// The secrets that will be added to the service
+    pub secrets: Vec<String>,
+    pub dns: Vec<DnsChange>,
+}
+
+impl Change {
+    /// Determine the changes between the configuration before and after the deployment
+    fn between(name: &ServiceName, before: Option<&Service>, after: Option<&Service>) -> Change {
+        let action = match (before, after) {
+            (_, None) => ChangeAction::Delete,
+            (None, Some(_)) => ChangeAction::Create,
+            (Some(_), Some(_)) => ChangeAction::Update,
+        };
+
+        let mut fields = Vec::new();
+        if let (Some(before), Some(after)) = (before, after) {
+            let before = serde_json::to_value(before).unwrap_or_default();
+            let after = serde_json::to_value(after).unwrap_or_default();
+            changed_fields("", &before, &after, &mut fields);
+        }
+
+        let mut secrets = after
+            .map(|after| {
+                after
+                    .secrets
+                    .keys()
+                    .filter(|k| !before.is_some_and(|b| b.secrets.contains_key(*k)))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        secrets.sort();
+
+        let before = before.map(|s| domains(name, s)).unwrap_or_default();
+        let after = after.map(|s| domains(name, s)).unwrap_or_default();
+        let removed = before.difference(&after).map(|domain| DnsChange {
+            action: DnsAction::Remove,
+            domain: domain.clone(),
+        });
+        let added = after.difference(&before).map(|domain| DnsChange {
+            action: DnsAction::Add,
+            domain: domain.clone(),
+        });
+
+        Change {
+            name: name.proper.clone(),
+            action,
+            fields,
+            secrets,
+            dns: removed.chain(added).collect(),
+        }
+    }
+}
+
+/// What will happen to a service
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// A DNS name that will be added or removed
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DnsChange {
+    pub action: DnsAction,
+    pub domain: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsAction {
+    Add,
+    Remove,
+}
+
+/// A service configuration that could not be parsed
+#[derive(Debug, Serialize)]
+pub struct ParseError {
+    pub path: String,
+    pub message: String,
+}
+
+/// Preview the changes between two commits without deploying anything
+#[instrument]
+pub async fn preview(before: &str, after: &str) -> Result<Plan, git2::Error> {
+    let files = git::instance()
+        .diff(before.to_string(), after.to_string())
+        .await?;
+
+    let mut plan = Plan::default();
+    let mut profiles = HashSet::new();
+    for diff in files {
+        if diff.binary || diff.path.extension().and_then(OsStr::to_str) != Some("toml") {
+            debug!(path = %diff.path.display(), "skipping non-service file");
+            continue;
+        }
+
+        if let Some(profile) = Profile::name(&diff.path) {
+            profiles.insert(profile);
+            continue;
+        }
+
+        match diff.action {
+            Action::Modified | Action::Deleted => {
+                let name = Service::name(&diff.path);
+                plan.compare(&name, &diff.path, before, after).await?;
+            }
+            Action::Unknown => continue,
+        }
+    }
+
+    // Services that include a changed profile will be redeployed as well
+    for path in dependents(after, &profiles).await? {
+        let name = Service::name(&path);
+        let planned = plan.services.iter().any(|c| c.name == name.proper)
+            || plan.errors.iter().any(|e| Path::new(&e.path) == path);
+        if !planned {
+            plan.compare(&name, &path, before, after).await?;
+        }
+    }
+
+    plan.services.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(plan)
+}
+
+impl Plan {
+    /// Add the changes to a service between the two commits
+    async fn compare(
+        &mut self,
+        name: &ServiceName,
+        path: &Path,
+        before: &str,
+        after: &str,
+    ) -> Result<(), git2::Error> {
+        // The previous version was already deployed, so it is assumed to be valid
+        let previous = read(before, path).await?.and_then(Result::ok);
+
+        match read(after, path).await? {
+            Some(Ok(service)) => {
+                let change = Change::between(name, previous.as_ref(), Some(&service));
+                self.services.push(change);
+            }
+            Some(Err(e)) => self.errors.push(ParseError {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            }),
+            None => {
+                let change = Change::between(name, previous.as_ref(), None);
+                self.services.push(change);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the services that include any of the profiles as of a commit, returning the paths to
+/// their configurations. Services that can't be parsed are skipped as it isn't known what they
+/// include.
+pub async fn dependents(
+    commit: &str,
+    profiles: &HashSet<String>,
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let mut dependents = Vec::new();
+    if profiles.is_empty() {
+        return Ok(dependents);
+    }
+
+    let repository = git::instance();
+    for path in repository.list(commit.to_string()).await? {
+        if path.extension().and_then(OsStr::to_str) != Some("toml")
+            || path.starts_with(profile::DIRECTORY)
+        {
+            continue;
+        }
+
+        let raw = match repository.read(commit.to_string(), path.clone()).await? {
+            Some(raw) => raw,
+            None => continue,
+        };
+        match Service::from_slice(&raw) {
+            Ok(service) if service.include.iter().any(|p| profiles.contains(p)) => {
+                dependents.push(path)
+            }
+            Ok(_) => {}
+            Err(e) => debug!(error = %e, path = %path.display(), "skipping unparseable service"),
+        }
+    }
+
+    Ok(dependents)
+}
+
+/// Read a service as of a commit, merging in the profiles it includes from the same commit.
+/// Nothing is returned if the service doesn't exist at the commit.
+async fn read(commit: &str, path: &Path) -> Result<Option<anyhow::Result<Service>>, git2::Error> {
+    let repository = git::instance();
+
+    let raw = match repository.read(commit.to_string(), path.to_owned()).await? {
+        Some(raw) => raw,
+        None => return Ok(None),
+    };
+    let mut service = match Service::from_slice(&raw) {
+        Ok(service) => service,
+        Err(e) => return Ok(Some(Err(e))),
+    };
+
+    for name in service.include.clone() {
+        let path = match Profile::path(&name) {
+            Ok(path) => path,
+            Err(e) => return Ok(Some(Err(e))),
+        };
+        let profile = match repository.read(commit.to_string(), path).await? {
+            Some(raw) => Profile::from_slice(&raw)
+                .map_err(|e| anyhow!("failed to parse profile {:?}: {}", name, e)),
+            None => Err(anyhow!("unknown profile {:?}", name)),
+        };
+
+        match profile {
+            Ok(profile) => service.apply_profile(&profile),
+            Err(e) => return Ok(Some(Err(e))),
+        }
+    }
+
+    Ok(Some(Ok(service)))
+}
+
+/// The DNS names a service can be reached at
+fn domains(name: &ServiceName, service: &Service) -> BTreeSet<String> {
+    let config = config::instance();
+
+    let mut domains = BTreeSet::new();
+    if service.kind == Kind::Job {
+        return domains;
+    }
+
+    domains.insert(format!("{}.{}", name.domain, config.dns.zone));
+    if service.web.enabled {
+        let domain = match &service.web.domain {
+            Some(domain) => domain.clone(),
+            None => format!("{}.{}", name.domain, config.deployment.domain),
+        };
+        domains.insert(domain);
+    }
+
+    domains
+}
+
+/// Find the paths to all the values that differ between two JSON documents
+fn changed_fields(prefix: &str, before: &Value, after: &Value, changed: &mut Vec<String>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let keys = before.keys().chain(after.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                let path = match prefix {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", prefix, key),
+                };
+                changed_fields(
+                    &path,
+                    before.get(key).unwrap_or(&Value::Null),
+                    after.get(key).unwrap_or(&Value::Null),
+                    changed,
+                );
+            }
+        }
+        (before, after) if before != after => changed.push(prefix.to_owned()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changed_fields;
+    use serde_json::json;
+
+    #[test]
+    fn nested_fields() {
+        let before = json!({
+            "docker": { "image": "wafflehacks/cms", "tag": "develop" },
+            "replicas": 1,
+            "secrets": { "key": "load" },
+        });
+        let after = json!({
+            "docker": { "image": "wafflehacks/cms", "tag": "main" },
+            "replicas": 1,
+            "secrets": { "key": "load", "token": "load" },
+            "volumes": [],
+        });
+
+        let mut changed = Vec::new();
+        changed_fields("", &before, &after, &mut changed);
+        assert_eq!(changed, vec!["docker.tag", "secrets.token", "volumes"]);
+    }
+
+    #[test]
+    fn identical() {
+        let value = json!({ "docker": { "image": "wafflehacks/cms" }, "replicas": [1, 2] });
+
+        let mut changed = Vec::new();
+        changed_fields("", &value, &value, &mut changed);
+        assert!(changed.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    deployer,
+    processor::jobs::{self, ScheduledRun},
+    service::{registry::REGISTRY, Kind, Schedule},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::broadcast::Receiver,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{debug, error, info, instrument};
+
+/// How often to check for jobs that need to run
+const TICK: Duration = Duration::from_secs(1);
+
+/// A job that is tracked by the scheduler
+struct Scheduled {
+    schedule: Schedule,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Dispatch runs of scheduled jobs whenever their schedules fire
+#[instrument(skip(stop))]
+pub async fn watch(mut stop: Receiver<()>) {
+    let mut scheduled = HashMap::new();
+    let mut interval = time::interval(TICK);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    select! {
+        _ = async {
+            loop {
+                interval.tick().await;
+                tick(&mut scheduled).await;
+            }
+        } => {}
+        _ = stop.recv() => {
+            info!("stopped scheduler");
+        }
+    }
+}
+
+/// Dispatch any runs that are due, picking up any changes to the registry
+async fn tick(scheduled: &mut HashMap<String, Scheduled>) {
+    let now = Utc::now();
+    let reg = REGISTRY.read().await;
+
+    for (name, service) in reg.iter() {
+        let schedule = match &service.schedule {
+            Some(schedule) if service.kind == Kind::Job => schedule,
+            _ => continue,
+        };
+
+        match scheduled.get_mut(name) {
+            Some(entry) if &entry.schedule == schedule => {
+                if let Some(at) = entry.next.filter(|at| *at <= now) {
+                    debug!(%name, %at, "schedule fired");
+                    jobs::dispatch(ScheduledRun::new(name.into(), at));
+                    entry.next = schedule.next(&now);
+                }
+            }
+            Some(entry) => {
+                info!(%name, "schedule changed");
+                entry.schedule = schedule.clone();
+                entry.next = schedule.next(&now);
+            }
+            None => {
+                // Runs that were restored from the queue haven't been recorded in the history yet
+                let dispatched = dispatched(name);
+                let missed = missed(name, schedule, &now).await;
+                for at in missed.into_iter().filter(|at| !dispatched.contains(at)) {
+                    info!(%name, %at, "catching up on missed run");
+                    jobs::dispatch(ScheduledRun::new(name.into(), at));
+                }
+
+                let entry = Scheduled {
+                    schedule: schedule.clone(),
+                    next: schedule.next(&now),
+                };
+                scheduled.insert(name.clone(), entry);
+            }
+        }
+    }
+
+    // Stop tracking anything that is no longer scheduled
+    scheduled.retain(
+        |name, _| matches!(reg.get(name), Some(s) if s.kind == Kind::Job && s.schedule.is_some()),
+    );
+}
+
+/// The firings of a job that were dispatched but haven't finished running
+fn dispatched(name: &str) -> HashSet<DateTime<Utc>> {
+    jobs::instance()
+        .unfinished()
+        .into_iter()
+        .filter(|record| record.name == "scheduled_run" && record.service.as_deref() == Some(name))
+        .filter_map(|record| serde_json::from_value::<ScheduledRun>(record.job).ok())
+        .map(|run| run.at())
+        .collect()
+}
+
+/// Find the runs of a newly tracked job that were missed since it last ran
+async fn missed(name: &str, schedule: &Schedule, now: &DateTime<Utc>) -> Vec<DateTime<Utc>> {
+    let history = match deployer::instance().history(name).await {
+        Ok(history) => history,
+        Err(e) => {
+            error!(%name, error = %e, "failed to load history, not catching up on missed runs");
+            return Vec::new();
+        }
+    };
+
+    match history.last() {
+        Some(entry) => schedule.missed(&Utc.timestamp(entry.timestamp as i64, 0), now),
+        None => Vec::new(),
+    }
+}
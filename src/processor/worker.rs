@@ -1,23 +1,47 @@
-use crate::processor::jobs;
+use crate::{
+    config,
+    processor::jobs::{self, Attempt, Retryable},
+};
 use tokio::{select, sync::broadcast::Receiver};
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
-/// Process incoming job workloads
+/// Process incoming job workloads. Once told to stop, no new jobs are taken but the current
+/// job is left to finish so it doesn't stop halfway through.
 #[instrument(skip(stop))]
 pub async fn worker(id: u32, mut stop: Receiver<()>) {
     info!("started worker {}", id);
 
+    let policy = &config::instance().agent.retry;
     let queue = jobs::instance();
     loop {
-        select! {
+        let queued = select! {
+            biased;
+
             _ = stop.recv() => {
                 info!("worker stopping");
                 break;
             }
-            job = queue.pop() => {
-                info!(name = job.name(), "received new job");
-                job.run().await;
+            queued = queue.pop(id) => queued,
+        };
+        info!(
+            id = queued.id,
+            name = queued.job.name(),
+            retries = queued.retries,
+            "received new job"
+        );
+
+        let attempt = Attempt {
+            id: queued.id,
+            last: queued.retries >= policy.max_retries,
+        };
+        let result = jobs::scope(Some(attempt), queued.job.run()).await;
+        match result {
+            Err(e) if e.is_retryable() && !attempt.last => {
+                let delay = policy.backoff(queued.retries);
+                warn!(id = queued.id, error = %e, ?delay, "job failed, retrying");
+                queue.retry(queued, &e, delay);
             }
+            result => queue.finish(&queued, &result),
         }
     }
 }
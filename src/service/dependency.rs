@@ -1,8 +1,9 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// A simple dependency that can be toggled on or off with a boolean, or implicitly enabled
 /// by specifying an environment variable name.
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum SimpleDependency {
     State(bool),
@@ -29,8 +30,8 @@ impl Default for SimpleDependency {
 /// it can be explicitly enabled with a default environment variable name, or implicitly enabled
 /// with a custom environment variable name. However, it can also take a custom role to pull
 /// credentials from which will also implicitly enable it.
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, untagged)]
 pub enum DynamicDependency {
     State(bool),
     Rename(String),
@@ -128,9 +129,9 @@ mod tests {
             };
         }
 
-        let raw = fs::read("./testdata/service/dependency_dynamic.toml")
+        let raw = fs::read_to_string("./testdata/service/dependency_dynamic.toml")
             .expect("failed to parse test service");
-        let parsed = toml::from_slice::<DynamicTest>(&raw).expect("failed to parse toml");
+        let parsed = toml::from_str::<DynamicTest>(&raw).expect("failed to parse toml");
 
         run!(parsed.state_false; none);
         run!(parsed.state_true; default);
@@ -141,9 +142,9 @@ mod tests {
 
     #[test]
     fn simple() {
-        let raw = fs::read("./testdata/service/dependency_simple.toml")
+        let raw = fs::read_to_string("./testdata/service/dependency_simple.toml")
             .expect("failed to parse test service");
-        let parsed = toml::from_slice::<SimpleTest>(&raw).expect("failed to parse toml");
+        let parsed = toml::from_str::<SimpleTest>(&raw).expect("failed to parse toml");
 
         assert_eq!(parsed.state_false.resolve("test"), None);
         assert_eq!(parsed.state_true.resolve("test"), Some("test"));
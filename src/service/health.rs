@@ -0,0 +1,209 @@
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject},
+    JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+/// How a service's health should be checked before it is allowed to replace the
+/// previous deployment.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Health {
+    #[serde(flatten)]
+    pub check: Check,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default)]
+    start_period: u64,
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+impl Health {
+    /// How long to wait between checks
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    /// How long to wait after starting before running the first check
+    pub fn start_period(&self) -> Duration {
+        Duration::from_secs(self.start_period)
+    }
+
+    /// How long a single check can take before it is considered failed
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl JsonSchema for Health {
+    fn schema_name() -> String {
+        "Health".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        // Each check rejects the fields it doesn't know about, so the options shared between
+        // them have to be added to every check rather than alongside them
+        let shared = [
+            (
+                "interval",
+                gen.subschema_for::<u64>(),
+                default_interval().into(),
+            ),
+            (
+                "retries",
+                gen.subschema_for::<u32>(),
+                default_retries().into(),
+            ),
+            ("start_period", gen.subschema_for::<u64>(), 0.into()),
+            (
+                "timeout",
+                gen.subschema_for::<u64>(),
+                default_timeout().into(),
+            ),
+        ];
+
+        let mut schema = Check::json_schema(gen).into_object();
+        let checks = schema.subschemas().one_of.iter_mut().flatten();
+        for check in checks {
+            if let Schema::Object(check) = check {
+                for (name, option, default) in &shared {
+                    check
+                        .object()
+                        .properties
+                        .insert(name.to_string(), with_default(option, default));
+                }
+            }
+        }
+
+        schema.metadata().description = Some(
+            "How a service's health should be checked before it is allowed to replace the \
+             previous deployment."
+                .to_owned(),
+        );
+        schema.into()
+    }
+}
+
+/// The possible ways of checking a service's health
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum Check {
+    Http { path: String, port: Option<u16> },
+    Tcp { port: Option<u16> },
+    Exec { command: Vec<String> },
+}
+
+impl Check {
+    /// Get the type of check
+    pub fn name<'a>(&self) -> &'a str {
+        match self {
+            Check::Http { .. } => "http",
+            Check::Tcp { .. } => "tcp",
+            Check::Exec { .. } => "exec",
+        }
+    }
+}
+
+fn with_default(schema: &Schema, default: &Value) -> Schema {
+    let mut schema: SchemaObject = schema.clone().into_object();
+    schema.metadata().default = Some(default.clone());
+    schema.into()
+}
+
+fn default_interval() -> u64 {
+    10
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Check, Health};
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Debug, Deserialize)]
+    struct Wrapped {
+        health: Health,
+    }
+
+    #[test]
+    fn deserialize_http() {
+        let src = r#"
+        [health]
+        type = "http"
+        path = "/health"
+        interval = 5
+        retries = 10
+        start_period = 15
+        "#;
+        let parsed: Wrapped = toml::from_str(src).unwrap();
+
+        assert_eq!(
+            parsed.health.check,
+            Check::Http {
+                path: "/health".into(),
+                port: None
+            }
+        );
+        assert_eq!(parsed.health.interval(), Duration::from_secs(5));
+        assert_eq!(parsed.health.retries, 10);
+        assert_eq!(parsed.health.start_period(), Duration::from_secs(15));
+        assert_eq!(parsed.health.timeout(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn deserialize_tcp() {
+        let src = r#"
+        [health]
+        type = "tcp"
+        port = 5432
+        "#;
+        let parsed: Wrapped = toml::from_str(src).unwrap();
+
+        assert_eq!(parsed.health.check, Check::Tcp { port: Some(5432) });
+        assert_eq!(parsed.health.interval(), Duration::from_secs(10));
+        assert_eq!(parsed.health.retries, 3);
+        assert_eq!(parsed.health.start_period(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn deserialize_exec() {
+        let src = r#"
+        health = { type = "exec", command = ["pg_isready", "-q"], timeout = 2 }
+        "#;
+        let parsed: Wrapped = toml::from_str(src).unwrap();
+
+        assert_eq!(
+            parsed.health.check,
+            Check::Exec {
+                command: vec!["pg_isready".into(), "-q".into()]
+            }
+        );
+        assert_eq!(parsed.health.timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn deserialize_unknown_field() {
+        let src = r#"
+        [health]
+        type = "tcp"
+        port = 5432
+        path = "/health"
+        "#;
+        let error = toml::from_str::<Wrapped>(src).unwrap_err();
+
+        assert!(error.to_string().contains("unknown field `path`"));
+    }
+}
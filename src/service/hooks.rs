@@ -0,0 +1,30 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Commands to run at different points of a service's deployment
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_deploy: Option<Hook>,
+}
+
+/// A command that is run in a temporary container from the service's image
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub command: Vec<String>,
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+impl Hook {
+    /// How long the command can run before it is considered failed
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+fn default_timeout() -> u64 {
+    300
+}
@@ -1,31 +1,68 @@
 use crate::config;
 use globset::{Glob, GlobSet, GlobSetBuilder};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr, NoneAsEmptyString};
 use std::fmt::Debug;
-use std::{collections::HashMap, ffi::OsStr, path::Path};
+use std::{collections::HashMap, ffi::OsStr, path::Path, str};
 use tokio::fs;
 
 mod dependency;
+mod health;
+mod hooks;
 mod name;
+pub mod persisted;
+pub mod profile;
 pub mod registry;
+mod resources;
+mod schedule;
 mod secret;
+pub mod template;
+mod validate;
+mod volume;
 
 use dependency::*;
+pub use health::{Check as HealthCheck, Health};
+pub use hooks::{Hook, Hooks};
 pub use name::ServiceName;
+pub use profile::Profile;
+pub use resources::{Bytes, Resources};
+pub use schedule::Schedule;
 pub use secret::{Format, Part as AWSPart, Secret};
+pub use validate::{validate, Issue};
+pub use volume::Volume;
 
 /// The configuration for a service
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Service {
     #[serde(default)]
     pub dependencies: Dependencies,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
     pub docker: Docker,
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    pub health: Option<Health>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub kind: Kind,
+    #[serde(default = "default_replicas")]
+    #[schemars(range(min = 1))]
+    pub replicas: u32,
+    #[serde(default)]
+    pub resources: Resources,
+    pub schedule: Option<Schedule>,
     #[serde(default)]
     pub secrets: HashMap<String, Secret>,
     #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub volumes: Vec<Volume>,
+    #[serde(default)]
     pub web: Web,
 }
 
@@ -33,7 +70,39 @@ impl Service {
     /// Parse a service configuration from a given file
     pub async fn parse<P: AsRef<Path>>(path: P) -> anyhow::Result<Service> {
         let raw = fs::read(path).await?;
-        Ok(toml::from_slice(&raw)?)
+        Service::from_slice(&raw)
+    }
+
+    /// Parse a service configuration from a file in the repository at the given root, merging
+    /// in any profiles it includes
+    pub async fn load(root: &Path, path: &Path) -> anyhow::Result<Service> {
+        let mut service = Service::parse(path).await?;
+        for name in service.include.clone() {
+            let profile = Profile::load(root, &name).await?;
+            service.apply_profile(&profile);
+        }
+
+        Ok(service)
+    }
+
+    /// Merge a profile into the service. Anything already set by the service or a previously
+    /// applied profile takes precedence.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        for (k, v) in &profile.environment {
+            if !self.environment.keys().any(|e| e.eq_ignore_ascii_case(k)) {
+                self.environment.insert(k.clone(), v.clone());
+            }
+        }
+        for (k, secret) in &profile.secrets {
+            if !self.secrets.keys().any(|s| s.eq_ignore_ascii_case(k)) {
+                self.secrets.insert(k.clone(), secret.clone());
+            }
+        }
+    }
+
+    /// Parse a service configuration from its raw contents
+    pub fn from_slice(raw: &[u8]) -> anyhow::Result<Service> {
+        Ok(toml::from_str(str::from_utf8(raw)?)?)
     }
 
     /// Generate the name of a service from its file path
@@ -52,8 +121,14 @@ impl Service {
     }
 }
 
+/// Generate the JSON Schema for service configuration files
+pub fn schema() -> RootSchema {
+    schema_for!(Service)
+}
+
 /// All the possible external dependencies a service can require.
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Dependencies {
     postgres: DynamicDependency,
     redis: SimpleDependency,
@@ -70,7 +145,8 @@ impl Dependencies {
 }
 
 /// The docker image configuration
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Docker {
     pub image: String,
     pub tag: String,
@@ -100,10 +176,12 @@ impl Docker {
 }
 
 #[serde_as]
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct AutoUpdate {
     #[serde(default)]
     #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[schemars(with = "Vec<String>")]
     pub additional_tags: Vec<Glob>,
     #[serde(default = "default_true")]
     pub automatic: bool,
@@ -118,16 +196,41 @@ impl Default for AutoUpdate {
     }
 }
 
+/// How the service's container is run
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// A long-running service that is kept up until it is replaced
+    #[default]
+    Service,
+    /// A task that runs to completion every time it is deployed
+    Job,
+}
+
+/// How a new deployment replaces the previous one
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Stop the previous deployment before starting the new one
+    #[default]
+    Recreate,
+    /// Run the new deployment alongside the previous one until it is ready to take over
+    BlueGreen,
+}
+
 #[serde_as]
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Web {
     #[serde(default = "default_true")]
     pub enabled: bool,
     #[serde(default)]
     #[serde_as(as = "NoneAsEmptyString")]
+    #[schemars(with = "Option<String>")]
     pub domain: Option<String>,
     #[serde(default)]
     #[serde_as(as = "NoneAsEmptyString")]
+    #[schemars(with = "Option<String>")]
     pub path: Option<String>,
 }
 
@@ -145,10 +248,18 @@ fn default_true() -> bool {
     true
 }
 
+fn default_replicas() -> u32 {
+    1
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Service;
+    use super::{
+        resources::Bytes, schedule::CatchUp, HealthCheck, Hooks, Kind, Resources, Secret, Service,
+        Strategy,
+    };
     use crate::service::dependency::ResolvedDependency;
+    use std::{path::Path, time::Duration};
 
     #[tokio::test]
     async fn deserialize() {
@@ -161,13 +272,31 @@ mod tests {
             Some(ResolvedDependency::new("DATABASE_URL", "testing"))
         );
         assert_eq!(service.dependencies.redis(), None);
+        assert_eq!(service.depends_on, vec!["wafflehacks/auth"]);
         assert_eq!(service.docker.image, "wafflehacks/cms");
         assert_eq!(service.docker.tag, "develop");
-        assert_eq!(service.docker.update.automatic, true);
+        assert!(service.docker.update.automatic);
         assert_eq!(service.docker.update.additional_tags.len(), 1);
-        assert_eq!(service.environment.len(), 4);
+        assert_eq!(service.environment.len(), 5);
+        assert_eq!(
+            service.health.map(|h| h.check),
+            Some(HealthCheck::Http {
+                path: "/health".into(),
+                port: None
+            })
+        );
+        let hook = service.hooks.pre_deploy.expect("missing pre-deploy hook");
+        assert_eq!(hook.command, vec!["python", "manage.py", "migrate"]);
+        assert_eq!(hook.timeout(), Duration::from_secs(600));
+        assert_eq!(service.kind, Kind::Service);
+        assert_eq!(service.replicas, 2);
+        assert_eq!(service.resources.memory, Some(Bytes(512 * 1024 * 1024)));
+        assert_eq!(service.resources.cpu_shares, Some(512));
+        assert_eq!(service.schedule, None);
         assert_eq!(service.secrets.len(), 6);
-        assert_eq!(service.web.enabled, true);
+        assert_eq!(service.strategy, Strategy::BlueGreen);
+        assert_eq!(service.volumes.len(), 3);
+        assert!(service.web.enabled);
         assert_eq!(service.web.domain, Some("testing.wafflehacks.tech".into()));
         assert_eq!(service.web.path, Some("/testing".into()));
     }
@@ -180,14 +309,79 @@ mod tests {
 
         assert_eq!(service.dependencies.postgres("testing"), None);
         assert_eq!(service.dependencies.redis(), None);
+        assert!(service.depends_on.is_empty());
         assert_eq!(service.docker.image, "wafflehacks/cms");
         assert_eq!(service.docker.tag, "develop");
-        assert_eq!(service.docker.update.automatic, true);
+        assert!(service.docker.update.automatic);
         assert_eq!(service.docker.update.additional_tags.len(), 0);
         assert_eq!(service.environment.len(), 0);
+        assert_eq!(service.health, None);
+        assert_eq!(service.hooks, Hooks::default());
+        assert!(service.include.is_empty());
+        assert_eq!(service.kind, Kind::Service);
+        assert_eq!(service.replicas, 1);
+        assert_eq!(service.resources, Resources::default());
+        assert_eq!(service.schedule, None);
         assert_eq!(service.secrets.len(), 0);
-        assert_eq!(service.web.enabled, true);
+        assert_eq!(service.strategy, Strategy::Recreate);
+        assert_eq!(service.volumes.len(), 0);
+        assert!(service.web.enabled);
         assert_eq!(service.web.domain, None);
         assert_eq!(service.web.path, None);
     }
+
+    #[test]
+    fn schema_up_to_date() {
+        let generated = serde_json::to_string_pretty(&super::schema()).unwrap();
+        let committed = std::fs::read_to_string("./service.schema.json").unwrap();
+
+        assert_eq!(
+            committed.trim_end(),
+            generated,
+            "service.schema.json is out of date, regenerate it with `wafflectl schema`"
+        );
+    }
+
+    #[tokio::test]
+    async fn include_profiles() {
+        let root = Path::new("./testdata/repository");
+        let service = Service::load(root, &root.join("wafflehacks/mailer.toml"))
+            .await
+            .expect("failed to load service");
+
+        assert_eq!(service.include, vec!["email", "sentry"]);
+        assert_eq!(service.environment.len(), 4);
+        assert_eq!(service.environment["smtp_host"], "smtp.wafflehacks.tech");
+        assert_eq!(service.environment["smtp_port"], "2525");
+        assert_eq!(service.environment["sentry_environment"], "production");
+        assert_eq!(service.secrets.len(), 2);
+        assert_eq!(service.secrets["smtp_password"], Secret::Load);
+    }
+
+    #[tokio::test]
+    async fn include_missing_profile() {
+        let root = Path::new("./testdata/repository");
+        let error = Service::load(root, &root.join("wafflehacks/broken.toml"))
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("\"missing\""));
+    }
+
+    #[tokio::test]
+    async fn job() {
+        let service = Service::parse("./testdata/service/job.toml")
+            .await
+            .expect("failed to parse service");
+
+        assert_eq!(service.kind, Kind::Job);
+        let schedule = service.schedule.expect("missing schedule");
+        assert_eq!(schedule.cron.to_string(), "0 0 3 * * *");
+        assert_eq!(schedule.catch_up, CatchUp::Once);
+        assert_eq!(
+            service.dependencies.postgres("testing"),
+            Some(ResolvedDependency::new("POSTGRES_URL", "testing"))
+        );
+        assert_eq!(service.secrets.len(), 1);
+    }
 }
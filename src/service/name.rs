@@ -1,4 +1,5 @@
 use itertools::Itertools;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use shrinkwraprs::Shrinkwrap;
 use std::fmt::{Debug, Display, Formatter};
 
@@ -46,6 +47,18 @@ impl Display for ServiceName {
     }
 }
 
+impl Serialize for ServiceName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.proper)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServiceName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(ServiceName::new)
+    }
+}
+
 impl From<&str> for ServiceName {
     fn from(name: &str) -> Self {
         ServiceName::new(name)
@@ -0,0 +1,221 @@
+use super::{schema, Service};
+use once_cell::sync::Lazy;
+use schemars::{
+    schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec},
+    Map,
+};
+use serde::{de::Error, Deserialize, Deserializer};
+use serde_json::Value;
+
+/// The schema of a service configuration, used to find the fields it no longer knows about
+static SCHEMA: Lazy<RootSchema> = Lazy::new(schema);
+
+/// Deserialize a service that was persisted by the agent, such as in the deployment history or
+/// a queued job. Unlike configurations from the repository, any fields that are no longer known
+/// are dropped instead of rejected, so configurations persisted by an older version can still
+/// be read.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Service, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut value = Value::deserialize(deserializer)?;
+    let root = Schema::Object(SCHEMA.schema.clone());
+    prune(&SCHEMA.definitions, &root, &mut value);
+
+    serde_json::from_value(value).map_err(D::Error::custom)
+}
+
+/// Remove any fields from the value that aren't allowed by the schema
+fn prune(definitions: &Map<String, Schema>, schema: &Schema, value: &mut Value) {
+    let schema = match resolve(definitions, schema) {
+        Some(schema) => schema,
+        None => return,
+    };
+
+    if let Some(subschemas) = &schema.subschemas {
+        for subschema in subschemas.all_of.iter().flatten() {
+            prune(definitions, subschema, value);
+        }
+
+        // Only the alternative the value was serialized from can be used to prune it
+        let alternative = subschemas
+            .one_of
+            .iter()
+            .chain(&subschemas.any_of)
+            .flatten()
+            .find(|alternative| fits(definitions, alternative, value));
+        if let Some(alternative) = alternative {
+            prune(definitions, alternative, value);
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            let object = match &schema.object {
+                Some(object) => object,
+                None => return,
+            };
+
+            if let Some(Schema::Bool(false)) = object.additional_properties.as_deref() {
+                fields.retain(|name, _| object.properties.contains_key(name));
+            }
+            for (name, field) in fields.iter_mut() {
+                let field_schema = object
+                    .properties
+                    .get(name)
+                    .or(object.additional_properties.as_deref());
+                if let Some(field_schema) = field_schema {
+                    prune(definitions, field_schema, field);
+                }
+            }
+        }
+        Value::Array(items) => {
+            let items_schema = schema.array.as_ref().and_then(|a| a.items.as_ref());
+            if let Some(SingleOrVec::Single(items_schema)) = items_schema {
+                for item in items {
+                    prune(definitions, items_schema, item);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the value could have been serialized from the schema, going by its type, the
+/// required fields, and the values of any tags
+fn fits(definitions: &Map<String, Schema>, schema: &Schema, value: &Value) -> bool {
+    let schema = match resolve(definitions, schema) {
+        Some(schema) => schema,
+        None => return matches!(schema, Schema::Bool(true)),
+    };
+
+    if let Some(types) = &schema.instance_type {
+        let matches = |instance_type| types.contains(&instance_type);
+        let fits_type = match value {
+            Value::Null => matches(InstanceType::Null),
+            Value::Bool(_) => matches(InstanceType::Boolean),
+            Value::Number(n) => {
+                matches(InstanceType::Number)
+                    || ((n.is_u64() || n.is_i64()) && matches(InstanceType::Integer))
+            }
+            Value::String(_) => matches(InstanceType::String),
+            Value::Array(_) => matches(InstanceType::Array),
+            Value::Object(_) => matches(InstanceType::Object),
+        };
+        if !fits_type {
+            return false;
+        }
+    }
+
+    if let Some(values) = &schema.enum_values {
+        if !values.contains(value) {
+            return false;
+        }
+    }
+
+    if let (Value::Object(fields), Some(object)) = (value, &schema.object) {
+        if !object.required.iter().all(|name| fields.contains_key(name)) {
+            return false;
+        }
+
+        // Tagged enums are told apart by the value of their tag
+        for (name, property) in &object.properties {
+            let tag = resolve(definitions, property).and_then(|p| p.enum_values.as_ref());
+            if let (Some(field), Some(tag)) = (fields.get(name), tag) {
+                if !tag.contains(field) {
+                    return false;
+                }
+            }
+        }
+    }
+
+    match &schema.subschemas {
+        Some(subschemas) => {
+            let all = subschemas
+                .all_of
+                .iter()
+                .flatten()
+                .all(|subschema| fits(definitions, subschema, value));
+            let mut alternatives = subschemas
+                .one_of
+                .iter()
+                .chain(&subschemas.any_of)
+                .peekable();
+            let any = alternatives.peek().is_none()
+                || alternatives
+                    .flatten()
+                    .any(|alternative| fits(definitions, alternative, value));
+            all && any
+        }
+        None => true,
+    }
+}
+
+/// Follow any references to the schema's definition
+fn resolve<'s>(
+    definitions: &'s Map<String, Schema>,
+    schema: &'s Schema,
+) -> Option<&'s SchemaObject> {
+    let mut schema = match schema {
+        Schema::Object(schema) => schema,
+        Schema::Bool(_) => return None,
+    };
+
+    while let Some(reference) = &schema.reference {
+        let name = reference.strip_prefix("#/definitions/")?;
+        schema = match definitions.get(name)? {
+            Schema::Object(schema) => schema,
+            Schema::Bool(_) => return None,
+        };
+    }
+
+    Some(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deserialize;
+    use crate::service::{HealthCheck, Secret, Service};
+    use serde_json::{json, Value};
+
+    fn persisted() -> Value {
+        let raw = std::fs::read("./example-service.toml").unwrap();
+        let service = Service::from_slice(&raw).unwrap();
+        serde_json::to_value(service).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let value = persisted();
+        let service = deserialize(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(service).unwrap(), value);
+    }
+
+    #[test]
+    fn ignore_removed_fields() {
+        let mut value = persisted();
+        value["image_tag"] = json!("main");
+        value["docker"]["registry"] = json!("ghcr.io");
+        value["health"] = json!({
+            "type": "http",
+            "path": "/health",
+            "port": 8080,
+            "method": "GET",
+            "interval": 5,
+            "retries": 3,
+            "start_period": 0,
+            "timeout": 2,
+        });
+        value["secrets"]["token"] = json!({ "type": "load", "rotate": true });
+
+        // The same configuration from the repository would be rejected
+        assert!(serde_json::from_value::<Service>(value.clone()).is_err());
+
+        let service = deserialize(value).unwrap();
+        assert!(matches!(
+            service.health.map(|h| h.check),
+            Some(HealthCheck::Http { path, port: Some(8080) }) if path == "/health"
+        ));
+        assert!(matches!(service.secrets.get("token"), Some(Secret::Load)));
+    }
+}
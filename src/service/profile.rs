@@ -0,0 +1,182 @@
+use super::Secret;
+use anyhow::{bail, Context};
+use async_recursion::async_recursion;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+    str,
+};
+use tokio::fs;
+use tokio_stream::{wrappers::ReadDirStream, StreamExt};
+
+/// The directory at the root of the repository containing the profiles
+pub const DIRECTORY: &str = "_profiles";
+
+/// Environment variables and secrets that can be shared between services
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    #[serde(default)]
+    pub secrets: HashMap<String, Secret>,
+}
+
+impl Profile {
+    /// Load a profile by name from the repository at the given root
+    pub async fn load(root: &Path, name: &str) -> anyhow::Result<Profile> {
+        let raw = fs::read(root.join(Profile::path(name)?))
+            .await
+            .with_context(|| format!("failed to read profile {:?}", name))?;
+        Profile::from_slice(&raw).with_context(|| format!("failed to parse profile {:?}", name))
+    }
+
+    /// Parse a profile from its raw contents
+    pub fn from_slice(raw: &[u8]) -> anyhow::Result<Profile> {
+        Ok(toml::from_str(str::from_utf8(raw)?)?)
+    }
+
+    /// The path to a profile relative to the root of the repository. Profiles can be nested in
+    /// directories, but each part of the name must be a plain file or directory name so that
+    /// it can't point outside of the profiles directory.
+    pub fn path(name: &str) -> anyhow::Result<PathBuf> {
+        let plain = |part: &str| {
+            let mut components = Path::new(part).components();
+            matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            ) && !part.contains('\\')
+        };
+        if !name.split('/').all(plain) {
+            bail!("invalid profile name {:?}", name);
+        }
+
+        Ok(Path::new(DIRECTORY).join(format!("{}.toml", name)))
+    }
+
+    /// Get the name of the profile at a path relative to the root of the repository, if it
+    /// is one
+    pub fn name(path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(DIRECTORY).ok()?;
+        if relative.extension().and_then(OsStr::to_str) != Some("toml") {
+            return None;
+        }
+
+        let name = relative
+            .with_extension("")
+            .iter()
+            .map(OsStr::to_str)
+            .collect::<Option<Vec<_>>>()?
+            .join("/");
+        Some(name)
+    }
+}
+
+/// Load all the profiles from the repository at the given root
+pub async fn load_all(root: &Path) -> anyhow::Result<HashMap<String, Profile>> {
+    let mut profiles = HashMap::new();
+
+    let directory = root.join(DIRECTORY);
+    if fs::metadata(&directory).await.is_ok() {
+        load_dir(&mut profiles, root, &directory).await?;
+    }
+
+    Ok(profiles)
+}
+
+#[async_recursion]
+async fn load_dir(
+    profiles: &mut HashMap<String, Profile>,
+    root: &Path,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let entries = fs::read_dir(path).await?;
+    let mut stream = ReadDirStream::new(entries);
+
+    while let Some(entry) = stream.next().await {
+        let entry = entry?;
+        if entry.file_type().await?.is_dir() {
+            load_dir(profiles, root, &entry.path()).await?;
+            continue;
+        }
+
+        let path = entry.path();
+        if let Some(name) = path.strip_prefix(root).ok().and_then(Profile::name) {
+            let profile = Profile::load(root, &name).await?;
+            profiles.insert(name, profile);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_all, Profile};
+    use std::path::Path;
+
+    #[test]
+    fn name() {
+        assert_eq!(
+            Profile::name(Path::new("_profiles/email.toml")),
+            Some("email".into())
+        );
+        assert_eq!(
+            Profile::name(Path::new("_profiles/oauth/google.toml")),
+            Some("oauth/google".into())
+        );
+        assert_eq!(Profile::name(Path::new("_profiles/README.md")), None);
+        assert_eq!(Profile::name(Path::new("wafflehacks/cms.toml")), None);
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(
+            Profile::path("email").unwrap(),
+            Path::new("_profiles/email.toml")
+        );
+        assert_eq!(
+            Profile::path("oauth/google").unwrap(),
+            Path::new("_profiles/oauth/google.toml")
+        );
+
+        for name in [
+            "",
+            "../secrets",
+            "oauth/../../secrets",
+            "./email",
+            "/etc/passwd",
+            "oauth//google",
+            "oauth\\google",
+        ] {
+            assert!(
+                Profile::path(name).is_err(),
+                "{:?} should be rejected",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn load_outside_directory() {
+        let error = Profile::load(Path::new("./testdata/repository"), "../service")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("invalid profile name"));
+    }
+
+    #[tokio::test]
+    async fn load() {
+        let profiles = load_all(Path::new("./testdata/repository"))
+            .await
+            .expect("failed to load profiles");
+
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles["email"].environment.len(), 2);
+        assert_eq!(profiles["email"].secrets.len(), 1);
+        assert_eq!(profiles["sentry"].environment.len(), 1);
+    }
+}
@@ -1,4 +1,4 @@
-use super::Service;
+use super::{profile, Service};
 use crate::config;
 use anyhow::Result;
 use async_recursion::async_recursion;
@@ -16,21 +16,25 @@ pub static REGISTRY: Lazy<RwLock<HashMap<String, Service>>> =
 pub async fn init() -> Result<()> {
     let mut reg = REGISTRY.write().await;
 
-    load_dir(&mut reg, &config::instance().git.clone_to).await?;
+    let root = &config::instance().git.clone_to;
+    load_dir(&mut reg, root, root).await?;
 
     info!("loaded {} services", reg.len());
     Ok(())
 }
 
 #[async_recursion]
-async fn load_dir(reg: &mut HashMap<String, Service>, path: &Path) -> Result<()> {
+async fn load_dir(reg: &mut HashMap<String, Service>, root: &Path, path: &Path) -> Result<()> {
     let entries = fs::read_dir(path).await?;
     let mut stream = ReadDirStream::new(entries);
 
     while let Some(entry) = stream.next().await {
         let entry = entry?;
         if entry.file_type().await?.is_dir() {
-            load_dir(reg, &entry.path()).await?;
+            // Profiles are merged into the services that include them
+            if entry.path() != root.join(profile::DIRECTORY) {
+                load_dir(reg, root, &entry.path()).await?;
+            }
             continue;
         }
 
@@ -39,7 +43,7 @@ async fn load_dir(reg: &mut HashMap<String, Service>, path: &Path) -> Result<()>
         }
 
         let name = Service::name(&entry.path());
-        let service = Service::parse(&entry.path()).await?;
+        let service = Service::load(root, &entry.path()).await?;
 
         debug!("loaded service {}", &name);
         reg.insert(name.proper, service);
@@ -0,0 +1,116 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+/// Limits on the host resources a service can consume
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resources {
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[schemars(with = "Option<String>")]
+    pub memory: Option<Bytes>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[schemars(with = "Option<String>")]
+    pub memory_reservation: Option<Bytes>,
+    pub cpu_shares: Option<i64>,
+    pub cpu_period: Option<i64>,
+    pub cpu_quota: Option<i64>,
+    pub pids: Option<i64>,
+}
+
+/// An amount of memory in bytes. Can be parsed from a string with an optional
+/// unit suffix of `b`, `k`, `m`, or `g` (i.e. `512m`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bytes(pub u64);
+
+impl FromStr for Bytes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = s.trim().to_lowercase();
+        let raw = raw.strip_suffix('b').unwrap_or(&raw);
+
+        let (number, multiplier) = if let Some(n) = raw.strip_suffix('k') {
+            (n, 1024)
+        } else if let Some(n) = raw.strip_suffix('m') {
+            (n, 1024 * 1024)
+        } else if let Some(n) = raw.strip_suffix('g') {
+            (n, 1024 * 1024 * 1024)
+        } else {
+            (raw, 1)
+        };
+
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .map(Bytes)
+            .ok_or_else(|| format!("invalid memory size '{}'", s))
+    }
+}
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bytes, Resources};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Wrapped {
+        resources: Resources,
+    }
+
+    #[test]
+    fn parse_bytes() {
+        assert_eq!("1024".parse(), Ok(Bytes(1024)));
+        assert_eq!("1024b".parse(), Ok(Bytes(1024)));
+        assert_eq!("2k".parse(), Ok(Bytes(2 * 1024)));
+        assert_eq!("512m".parse(), Ok(Bytes(512 * 1024 * 1024)));
+        assert_eq!("512MB".parse(), Ok(Bytes(512 * 1024 * 1024)));
+        assert_eq!("1g".parse(), Ok(Bytes(1024 * 1024 * 1024)));
+        assert!("lots".parse::<Bytes>().is_err());
+        assert!("-1m".parse::<Bytes>().is_err());
+        assert!("18446744073709551615k".parse::<Bytes>().is_err());
+    }
+
+    #[test]
+    fn deserialize() {
+        let src = r#"
+        [resources]
+        memory = "512m"
+        cpu_shares = 512
+        pids = 100
+        "#;
+        let parsed: Wrapped = toml::from_str(src).unwrap();
+
+        assert_eq!(parsed.resources.memory, Some(Bytes(512 * 1024 * 1024)));
+        assert_eq!(parsed.resources.memory_reservation, None);
+        assert_eq!(parsed.resources.cpu_shares, Some(512));
+        assert_eq!(parsed.resources.cpu_period, None);
+        assert_eq!(parsed.resources.cpu_quota, None);
+        assert_eq!(parsed.resources.pids, Some(100));
+    }
+
+    #[test]
+    fn deserialize_invalid_memory() {
+        let src = r#"
+        [resources]
+        memory = "lots"
+        "#;
+
+        assert!(toml::from_str::<Wrapped>(src).is_err());
+    }
+}
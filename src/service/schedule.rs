@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+/// When a job should be run
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// The cron expression to run on, evaluated in UTC
+    #[serde_as(as = "DisplayFromStr")]
+    #[schemars(with = "String")]
+    pub cron: cron::Schedule,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+}
+
+impl Schedule {
+    /// The next time the job should run
+    pub fn next(&self, now: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.cron.after(now).next()
+    }
+
+    /// Find the runs that should be made up for after missing all the firings between
+    /// the last run and now
+    pub fn missed(&self, last: &DateTime<Utc>, now: &DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let missed = self.cron.after(last).take_while(|at| at <= now);
+
+        match self.catch_up {
+            CatchUp::Skip => Vec::new(),
+            CatchUp::Once => missed.last().into_iter().collect(),
+            CatchUp::All => missed.collect(),
+        }
+    }
+}
+
+/// What to do about firings that were missed while WaffleMaker was not running
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CatchUp {
+    /// Ignore any missed firings
+    #[default]
+    Skip,
+    /// Run once if any firings were missed
+    Once,
+    /// Run once for every missed firing
+    All,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CatchUp, Schedule};
+    use chrono::{TimeZone, Utc};
+
+    fn schedule(catch_up: CatchUp) -> Schedule {
+        Schedule {
+            cron: "0 0 * * * *".parse().unwrap(),
+            catch_up,
+        }
+    }
+
+    #[test]
+    fn next() {
+        let now = Utc.ymd(2022, 6, 1).and_hms(3, 30, 0);
+        assert_eq!(
+            schedule(CatchUp::Skip).next(&now),
+            Some(Utc.ymd(2022, 6, 1).and_hms(4, 0, 0))
+        );
+    }
+
+    #[test]
+    fn missed() {
+        let last = Utc.ymd(2022, 6, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2022, 6, 1).and_hms(3, 30, 0);
+
+        assert!(schedule(CatchUp::Skip).missed(&last, &now).is_empty());
+        assert_eq!(
+            schedule(CatchUp::Once).missed(&last, &now),
+            vec![Utc.ymd(2022, 6, 1).and_hms(3, 0, 0)]
+        );
+        assert_eq!(
+            schedule(CatchUp::All).missed(&last, &now),
+            vec![
+                Utc.ymd(2022, 6, 1).and_hms(1, 0, 0),
+                Utc.ymd(2022, 6, 1).and_hms(2, 0, 0),
+                Utc.ymd(2022, 6, 1).and_hms(3, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_missed() {
+        let last = Utc.ymd(2022, 6, 1).and_hms(3, 0, 0);
+        let now = Utc.ymd(2022, 6, 1).and_hms(3, 30, 0);
+
+        assert!(schedule(CatchUp::All).missed(&last, &now).is_empty());
+    }
+}
@@ -1,3 +1,8 @@
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
 use serde::{
     de::{value::MapAccessDeserializer, Deserializer, Error, MapAccess, Unexpected, Visitor},
     Deserialize, Serialize,
@@ -5,7 +10,7 @@ use serde::{
 use std::{fmt, str::FromStr};
 
 /// The possible formats that a secret can be encoded into.
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Format {
     Alphanumeric,
@@ -14,7 +19,7 @@ pub enum Format {
 }
 
 /// Which part of the pair to store in the variable
-#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Part {
     Access,
@@ -68,8 +73,8 @@ impl From<AuxiliarySecret> for Secret {
 
 /// `AuxiliarySecret` exists to avoid implementing the deserializer of the map by hand which
 /// means we cannot use `Secret` itself as it would cause infinite recursion.
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase", tag = "type")]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
 enum AuxiliarySecret {
     Aws {
         role: String,
@@ -119,6 +124,30 @@ impl<'de> Deserialize<'de> for Secret {
     }
 }
 
+impl JsonSchema for Secret {
+    fn schema_name() -> String {
+        "Secret".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        // Secrets without any configuration can also be written as just their type
+        let bare = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(vec!["load".into()]),
+            ..Default::default()
+        };
+
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![bare.into(), gen.subschema_for::<AuxiliarySecret>()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 impl FromStr for Secret {
     type Err = String;
 
@@ -159,9 +188,10 @@ mod tests {
         let parsed = toml::from_str::<Wrapped>(src).unwrap_err();
 
         assert_eq!(
-            parsed.to_string().as_str(),
-            "invalid value: string \"aws\", expected one of \'load\' for key `secret` at line 2 column 18"
+            parsed.message(),
+            "invalid value: string \"aws\", expected one of \'load\'"
         );
+        assert_eq!(parsed.span(), Some(18..23));
     }
 
     #[test]
@@ -184,7 +214,7 @@ mod tests {
         {
             assert_eq!(length, 16);
             assert_eq!(format, Format::Base64);
-            assert_eq!(regenerate, true);
+            assert!(regenerate);
         }
     }
 }
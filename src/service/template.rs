@@ -0,0 +1,260 @@
+use super::{Service, ServiceName};
+use std::collections::HashMap;
+
+/// Why a template could not be rendered
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("missing closing `}}}}`")]
+    Unclosed,
+    #[error("unknown reference `{0}`")]
+    UnknownReference(String),
+    #[error("reference cycle `{0}`")]
+    Cycle(String),
+}
+
+/// An environment variable that could not be rendered
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("failed to render environment variable {name}: {source}")]
+pub struct InvalidVariable {
+    pub name: String,
+    pub source: Error,
+}
+
+/// Substitute any references in the form `{{ name }}` with their value. Quoted references
+/// (i.e. `{{ "{{" }}`) are inserted as-is.
+pub fn render<F>(template: &str, mut lookup: F) -> Result<String, Error>
+where
+    F: FnMut(&str) -> Result<String, Error>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest.find("}}").ok_or(Error::Unclosed)?;
+        let reference = rest[..end].trim();
+        rest = &rest[end + 2..];
+
+        let quoted = reference.len() >= 2
+            && ((reference.starts_with('"') && reference.ends_with('"'))
+                || (reference.starts_with('\'') && reference.ends_with('\'')));
+        if quoted {
+            rendered.push_str(&reference[1..reference.len() - 1]);
+        } else {
+            rendered.push_str(&lookup(reference)?);
+        }
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// The values that can be referenced from a service's environment variables
+pub struct Variables<'s> {
+    /// The raw environment variables with their names made uppercase
+    environment: HashMap<String, &'s str>,
+    /// The secret values with their names made uppercase
+    secrets: HashMap<String, String>,
+    builtin: HashMap<&'static str, String>,
+    zone: &'s str,
+}
+
+impl<'s> Variables<'s> {
+    /// Collect the variables for a service given its loaded secrets, the default domain for
+    /// services, and the internal DNS zone
+    pub fn new(
+        name: &ServiceName,
+        service: &'s Service,
+        secrets: HashMap<String, String>,
+        domain: &str,
+        zone: &'s str,
+    ) -> Variables<'s> {
+        let environment = service
+            .environment
+            .iter()
+            .map(|(k, v)| (k.to_uppercase(), v.as_str()))
+            .collect();
+        let secrets = secrets
+            .into_iter()
+            .map(|(k, v)| (k.to_uppercase(), v))
+            .collect();
+
+        let public = match &service.web.domain {
+            Some(domain) => domain.clone(),
+            None => format!("{}.{}", name.domain, domain),
+        };
+        let builtin = HashMap::from([
+            ("service.name", name.proper.clone()),
+            ("service.domain", public),
+            ("service.internal", format!("{}.{}", name.domain, zone)),
+            ("image.name", service.docker.image.clone()),
+            ("image.tag", service.docker.tag.clone()),
+        ]);
+
+        Variables {
+            environment,
+            secrets,
+            builtin,
+            zone,
+        }
+    }
+
+    /// Render all of the environment variables, the names are made uppercase
+    pub fn environment(&self) -> Result<Vec<(String, String)>, InvalidVariable> {
+        let mut names = self.environment.keys().collect::<Vec<_>>();
+        names.sort_unstable();
+
+        names
+            .into_iter()
+            .map(|name| match self.resolve(name, &mut Vec::new()) {
+                Ok(value) => Ok((name.clone(), value)),
+                Err(source) => Err(InvalidVariable {
+                    name: name.clone(),
+                    source,
+                }),
+            })
+            .collect()
+    }
+
+    /// Render an environment variable, keeping track of the variables that are being rendered
+    /// to detect cycles
+    fn resolve(&self, name: &str, stack: &mut Vec<String>) -> Result<String, Error> {
+        let cycle = stack.iter().any(|n| n == name);
+        stack.push(name.to_owned());
+        if cycle {
+            return Err(Error::Cycle(stack.join(" -> ")));
+        }
+
+        let value = render(self.environment[name], |reference| {
+            self.lookup(reference, stack)
+        })?;
+
+        stack.pop();
+        Ok(value)
+    }
+
+    fn lookup(&self, reference: &str, stack: &mut Vec<String>) -> Result<String, Error> {
+        if let Some(name) = reference.strip_prefix("env.") {
+            let name = name.to_uppercase();
+            if self.environment.contains_key(&name) {
+                return self.resolve(&name, stack);
+            }
+        } else if let Some(name) = reference.strip_prefix("secrets.") {
+            if let Some(value) = self.secrets.get(&name.to_uppercase()) {
+                return Ok(value.clone());
+            }
+        } else if let Some(name) = reference.strip_prefix("dns.") {
+            if !name.is_empty() {
+                return Ok(format!("{}.{}", ServiceName::new(name).domain, self.zone));
+            }
+        } else if let Some(value) = self.builtin.get(reference) {
+            return Ok(value.clone());
+        }
+
+        Err(Error::UnknownReference(reference.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Error, InvalidVariable, Variables};
+    use crate::service::{Service, ServiceName};
+    use std::collections::HashMap;
+
+    fn service(environment: &str) -> Service {
+        let raw = format!(
+            "[docker]\nimage = \"wafflehacks/cms\"\ntag = \"main\"\n[environment]\n{}",
+            environment
+        );
+        Service::from_slice(raw.as_bytes()).unwrap()
+    }
+
+    fn variables(service: &Service) -> Variables<'_> {
+        let secrets = HashMap::from([("api_key".to_owned(), "hunter2".to_owned())]);
+        let name = ServiceName::new("wafflehacks/cms");
+        Variables::new(&name, service, secrets, "wafflehacks.tech", "internal")
+    }
+
+    #[test]
+    fn render_references() {
+        let lookup = |reference: &str| match reference {
+            "username" => Ok("cms".to_owned()),
+            _ => Err(Error::UnknownReference(reference.to_owned())),
+        };
+
+        assert_eq!(render("plain", lookup), Ok("plain".to_owned()));
+        assert_eq!(
+            render("{{username}}@{{ username }}", lookup),
+            Ok("cms@cms".to_owned())
+        );
+        assert_eq!(render("{{ \"{{\" }} }}", lookup), Ok("{{ }}".to_owned()));
+        assert_eq!(render("{{ username", lookup), Err(Error::Unclosed));
+        assert_eq!(
+            render("{{ password }}", lookup),
+            Err(Error::UnknownReference("password".to_owned()))
+        );
+    }
+
+    #[test]
+    fn environment() {
+        let service = service(
+            r#"
+            api_url = "https://{{ service.domain }}/api"
+            auth_url = "http://{{ dns.wafflehacks/auth }}:8000"
+            internal = "{{ service.internal }}"
+            key = "{{ secrets.API_KEY }}"
+            version = "{{ image.tag }}"
+            webhook_url = "{{ env.api_url }}/webhook"
+            "#,
+        );
+
+        assert_eq!(
+            variables(&service).environment().unwrap(),
+            vec![
+                (
+                    "API_URL".into(),
+                    "https://cms.wafflehacks.wafflehacks.tech/api".into()
+                ),
+                (
+                    "AUTH_URL".into(),
+                    "http://auth.wafflehacks.internal:8000".into()
+                ),
+                ("INTERNAL".into(), "cms.wafflehacks.internal".into()),
+                ("KEY".into(), "hunter2".into()),
+                ("VERSION".into(), "main".into()),
+                (
+                    "WEBHOOK_URL".into(),
+                    "https://cms.wafflehacks.wafflehacks.tech/api/webhook".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_reference() {
+        let service = service(r#"url = "{{ secrets.missing }}""#);
+
+        assert_eq!(
+            variables(&service).environment(),
+            Err(InvalidVariable {
+                name: "URL".into(),
+                source: Error::UnknownReference("secrets.missing".into())
+            })
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let service = service("a = \"{{ env.b }}\"\nb = \"{{ env.a }}\"");
+
+        assert_eq!(
+            variables(&service).environment(),
+            Err(InvalidVariable {
+                name: "A".into(),
+                source: Error::Cycle("A -> B -> A".into())
+            })
+        );
+    }
+}
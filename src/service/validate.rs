@@ -0,0 +1,319 @@
+use super::{template::Variables, AWSPart, Kind, Profile, Secret, Service, ServiceName};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A problem with a service configuration
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Issue {
+    /// The dotted path to the field with the problem, empty if it applies to the whole file
+    pub field: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Issue {
+        Issue {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Check a raw service configuration for any problems that would only otherwise be found
+/// once it is deployed. Included profiles are loaded by name using the given function.
+pub fn validate<F>(raw: &[u8], mut profile: F) -> Vec<Issue>
+where
+    F: FnMut(&str) -> anyhow::Result<Profile>,
+{
+    // Syntax errors and unknown fields are reported with their location by the parser
+    let mut service = match Service::from_slice(raw) {
+        Ok(service) => service,
+        Err(e) => return vec![Issue::new("", e.to_string())],
+    };
+
+    let mut issues = Vec::new();
+    for name in service.include.clone() {
+        match profile(&name) {
+            Ok(profile) => service.apply_profile(&profile),
+            Err(e) => issues.push(Issue::new("include", format!("{:#}", e))),
+        }
+    }
+
+    // The profiles must be merged in before checking the semantics as they can provide
+    // values that are referenced by the service
+    if issues.is_empty() {
+        check(&service, &mut issues);
+    }
+    issues
+}
+
+/// Check the semantics of an already parsed service
+fn check(service: &Service, issues: &mut Vec<Issue>) {
+    if let Err(e) = service.docker.allowed_tags() {
+        issues.push(Issue::new("docker.tag", e.to_string()));
+    }
+
+    if let Some(domain) = &service.web.domain {
+        if let Err(message) = check_domain(domain) {
+            issues.push(Issue::new("web.domain", message));
+        }
+    }
+    if let Some(path) = &service.web.path {
+        if let Err(message) = check_path(path) {
+            issues.push(Issue::new("web.path", message));
+        }
+    }
+
+    if service.replicas < 1 {
+        issues.push(Issue::new("replicas", "must run at least 1 replica"));
+    }
+
+    if service.schedule.is_some() && service.kind != Kind::Job {
+        issues.push(Issue::new("schedule", "only jobs can be scheduled"));
+    }
+
+    check_aws_pairs(service, issues);
+    check_templates(service, issues);
+}
+
+/// Ensure all the references in the environment variables exist
+fn check_templates(service: &Service, issues: &mut Vec<Issue>) {
+    // Only the references matter, not the values they are rendered with
+    let secrets = service
+        .secrets
+        .keys()
+        .map(|k| (k.clone(), String::new()))
+        .collect();
+    let name = ServiceName::new("validate");
+    let variables = Variables::new(&name, service, secrets, "localhost", "localhost");
+
+    if let Err(e) = variables.environment() {
+        let field = service
+            .environment
+            .keys()
+            .find(|k| k.to_uppercase() == e.name)
+            .cloned()
+            .unwrap_or(e.name);
+        issues.push(Issue::new(
+            format!("environment.{}", field),
+            e.source.to_string(),
+        ));
+    }
+}
+
+/// Ensure every AWS role has exactly one variable for each part of the key pair
+fn check_aws_pairs(service: &Service, issues: &mut Vec<Issue>) {
+    let mut roles = BTreeMap::<&str, (Vec<&str>, Vec<&str>)>::new();
+    for (name, secret) in &service.secrets {
+        if let Secret::Aws { role, part } = secret {
+            let (access, secret) = roles.entry(role).or_default();
+            match part {
+                AWSPart::Access => access.push(name),
+                AWSPart::Secret => secret.push(name),
+            }
+        }
+    }
+
+    for (role, (mut access, mut secret)) in roles {
+        access.sort_unstable();
+        secret.sort_unstable();
+
+        for (part, names, other) in [("access", &access, &secret), ("secret", &secret, &access)] {
+            match names.len() {
+                0 => issues.push(Issue::new(
+                    format!("secrets.{}", other[0]),
+                    format!(
+                        "missing the {} part of the key pair for role {:?}",
+                        part, role
+                    ),
+                )),
+                1 => {}
+                _ => issues.push(Issue::new(
+                    format!("secrets.{}", names[1]),
+                    format!(
+                        "the {} part of the key pair for role {:?} is already stored in {:?}",
+                        part, role, names[0]
+                    ),
+                )),
+            }
+        }
+    }
+}
+
+/// Check that a domain is a valid hostname
+fn check_domain(domain: &str) -> Result<(), String> {
+    if domain.len() > 253 {
+        return Err("must be at most 253 characters".into());
+    }
+
+    for label in domain.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("label {:?} must be 1 to 63 characters", label));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!(
+                "label {:?} can only contain letters, digits, and hyphens",
+                label
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!(
+                "label {:?} cannot start or end with a hyphen",
+                label
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that a path can be used as a routing prefix
+fn check_path(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err("must start with a `/`".into());
+    }
+    if let Some(c) = path
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || matches!(c, '`' | '?' | '#'))
+    {
+        return Err(format!("cannot contain {:?}", c));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_domain, check_path, Issue};
+    use crate::service::Profile;
+    use anyhow::anyhow;
+
+    const MINIMAL: &str = r#"
+        [docker]
+        image = "wafflehacks/cms"
+        tag = "develop"
+    "#;
+
+    fn validate(raw: &[u8]) -> Vec<Issue> {
+        super::validate(raw, |name| match name {
+            "email" => Profile::from_slice(b"[secrets]\nsmtp_password = \"load\""),
+            _ => Err(anyhow!("unknown profile {:?}", name)),
+        })
+    }
+
+    #[test]
+    fn example() {
+        let raw = std::fs::read("./example-service.toml").unwrap();
+        assert_eq!(validate(&raw), vec![]);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let raw = format!("{}\nimage_tag = \"main\"", MINIMAL);
+
+        let issues = validate(raw.as_bytes());
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("line 6, column 1"));
+        assert!(issues[0].message.contains("unknown field `image_tag`"));
+    }
+
+    #[test]
+    fn invalid_structure() {
+        let issues = validate(b"replicas = \"two\"");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("line 1, column 12"));
+    }
+
+    #[test]
+    fn no_replicas() {
+        let raw = format!("replicas = 0\n{}", MINIMAL);
+        assert_eq!(
+            validate(raw.as_bytes()),
+            vec![Issue::new("replicas", "must run at least 1 replica")]
+        );
+    }
+
+    #[test]
+    fn unpaired_aws_secrets() {
+        let raw = format!(
+            r#"{}
+            [secrets]
+            key = {{ type = "aws", role = "uploads", part = "access" }}
+            other_key = {{ type = "aws", role = "backups", part = "access" }}
+            other_secret = {{ type = "aws", role = "backups", part = "secret" }}
+            duplicate_secret = {{ type = "aws", role = "backups", part = "secret" }}
+            "#,
+            MINIMAL
+        );
+
+        assert_eq!(
+            validate(raw.as_bytes()),
+            vec![
+                Issue::new(
+                    "secrets.other_secret",
+                    "the secret part of the key pair for role \"backups\" is already stored in \"duplicate_secret\""
+                ),
+                Issue::new(
+                    "secrets.key",
+                    "missing the secret part of the key pair for role \"uploads\""
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_template_reference() {
+        let raw = format!(
+            "{}\n[environment]\napi_key = \"{{{{ secrets.api_key }}}}\"",
+            MINIMAL
+        );
+
+        assert_eq!(
+            validate(raw.as_bytes()),
+            vec![Issue::new(
+                "environment.api_key",
+                "unknown reference `secrets.api_key`"
+            )]
+        );
+    }
+
+    #[test]
+    fn included_profiles() {
+        let raw = format!(
+            "include = [\"email\"]\n{}\n[environment]\nsmtp = \"{{{{ secrets.smtp_password }}}}\"",
+            MINIMAL
+        );
+        assert_eq!(validate(raw.as_bytes()), vec![]);
+
+        let raw = format!("include = [\"email\", \"oauth\"]\n{}", MINIMAL);
+        assert_eq!(
+            validate(raw.as_bytes()),
+            vec![Issue::new("include", "unknown profile \"oauth\"")]
+        );
+    }
+
+    #[test]
+    fn invalid_tag_glob() {
+        let raw = MINIMAL.replace("develop", "sha-[");
+        assert_eq!(validate(raw.as_bytes())[0].field, "docker.tag");
+    }
+
+    #[test]
+    fn domains() {
+        assert!(check_domain("cms.wafflehacks.tech").is_ok());
+        assert!(check_domain("cms-2.wafflehacks.tech").is_ok());
+        assert!(check_domain("cms..wafflehacks.tech").is_err());
+        assert!(check_domain("-cms.wafflehacks.tech").is_err());
+        assert!(check_domain("cms_2.wafflehacks.tech").is_err());
+        assert!(check_domain("https://cms.wafflehacks.tech").is_err());
+    }
+
+    #[test]
+    fn paths() {
+        assert!(check_path("/testing").is_ok());
+        assert!(check_path("/api/v1").is_ok());
+        assert!(check_path("testing").is_err());
+        assert!(check_path("/testing?a=b").is_err());
+        assert!(check_path("/test ing").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use super::{default_true, resources::Bytes};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::path::PathBuf;
+
+/// The possible types of storage that can be mounted into a service's containers
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+pub enum Volume {
+    #[serde(rename = "volume")]
+    Named {
+        name: String,
+        target: String,
+        #[serde(default)]
+        read_only: bool,
+        #[serde(default = "default_true")]
+        retain: bool,
+    },
+    Bind {
+        source: PathBuf,
+        target: String,
+    },
+    Tmpfs {
+        target: String,
+        #[serde(default)]
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        #[schemars(with = "Option<String>")]
+        size: Option<Bytes>,
+    },
+}
+
+impl Volume {
+    /// Get the type of volume
+    pub fn name<'a>(&self) -> &'a str {
+        match self {
+            Volume::Named { .. } => "volume",
+            Volume::Bind { .. } => "bind",
+            Volume::Tmpfs { .. } => "tmpfs",
+        }
+    }
+
+    /// Where the volume is mounted inside the container
+    pub fn target(&self) -> &str {
+        match self {
+            Volume::Named { target, .. }
+            | Volume::Bind { target, .. }
+            | Volume::Tmpfs { target, .. } => target,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bytes, Volume};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Wrapped {
+        volumes: Vec<Volume>,
+    }
+
+    #[test]
+    fn deserialize() {
+        let src = r#"
+        [[volumes]]
+        type = "volume"
+        name = "data"
+        target = "/data"
+
+        [[volumes]]
+        type = "volume"
+        name = "cache"
+        target = "/cache"
+        read_only = true
+        retain = false
+
+        [[volumes]]
+        type = "bind"
+        source = "/srv/shared"
+        target = "/shared"
+
+        [[volumes]]
+        type = "tmpfs"
+        target = "/tmp"
+        size = "64m"
+        "#;
+        let parsed: Wrapped = toml::from_str(src).unwrap();
+
+        assert_eq!(
+            parsed.volumes,
+            vec![
+                Volume::Named {
+                    name: "data".into(),
+                    target: "/data".into(),
+                    read_only: false,
+                    retain: true,
+                },
+                Volume::Named {
+                    name: "cache".into(),
+                    target: "/cache".into(),
+                    read_only: true,
+                    retain: false,
+                },
+                Volume::Bind {
+                    source: "/srv/shared".into(),
+                    target: "/shared".into(),
+                },
+                Volume::Tmpfs {
+                    target: "/tmp".into(),
+                    size: Some(Bytes(64 * 1024 * 1024)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_invalid_type() {
+        let src = r#"
+        [[volumes]]
+        type = "nfs"
+        target = "/data"
+        "#;
+
+        assert!(toml::from_str::<Wrapped>(src).is_err());
+    }
+}
@@ -17,9 +17,10 @@ mod error;
 mod models;
 mod renewal;
 
-use error::{Error, Result};
+use error::Result;
 use models::*;
 
+pub use error::Error;
 pub use models::{Aws, Lease};
 pub use renewal::LEASES;
 
@@ -205,18 +206,7 @@ impl Vault {
 
         // Revoke any leases if the existed
         if let Some(leases) = revoked {
-            for lease in leases {
-                self.client
-                    .put(format!("{}v1/sys/leases/revoke", self.url))
-                    .json(&LeaseRevocation {
-                        lease_id: &lease.id,
-                    })
-                    .send()
-                    .await?
-                    .error_for_status()?;
-
-                info!(id = %lease.id, "revoked lease");
-            }
+            self.revoke(&leases).await?;
         }
 
         info!("revoked leases for container");
@@ -224,6 +214,24 @@ impl Vault {
         Ok(())
     }
 
+    /// Revoke leases that were never registered for renewal
+    pub async fn revoke(&self, leases: &[Lease]) -> Result<()> {
+        for lease in leases {
+            self.client
+                .put(format!("{}v1/sys/leases/revoke", self.url))
+                .json(&LeaseRevocation {
+                    lease_id: &lease.id,
+                })
+                .send()
+                .await?
+                .error_for_status()?;
+
+            info!(id = %lease.id, "revoked lease");
+        }
+
+        Ok(())
+    }
+
     /// Renew an individual lease for a new TTL
     #[instrument(skip(self, lease), fields(id = %lease.id))]
     async fn renew_lease(&self, lease: &Lease) -> Result<()> {
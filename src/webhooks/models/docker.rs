@@ -2,6 +2,7 @@ use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Docker {
+    #[allow(dead_code)]
     pub callback_url: String,
     pub push_data: PushData,
     pub repository: Repository,
@@ -30,10 +30,22 @@ pub enum Command {
     Add(commands::Add),
     /// Delete an object
     Delete(commands::Delete),
+    /// Run a command in a service's container
+    Exec(commands::Exec),
     /// Get details about an object
     Get(commands::Get),
+    /// Print the logs of a service
+    Logs(commands::Logs),
+    /// Preview what deploying a range of commits would change
+    Plan(commands::Plan),
     /// Run an object
     Run(commands::Run),
+    /// Print the JSON Schema for service configuration files
+    Schema(commands::Schema),
+    /// Check service configurations for problems without deploying them
+    Validate(commands::Validate),
+    /// Follow the progress of an object
+    Watch(commands::Watch),
 }
 
 impl Command {
@@ -41,8 +53,14 @@ impl Command {
         match self {
             Self::Add(s) => Box::new(s),
             Self::Delete(s) => Box::new(s),
+            Self::Exec(s) => Box::new(s),
             Self::Get(s) => Box::new(s),
+            Self::Logs(s) => Box::new(s),
+            Self::Plan(s) => Box::new(s),
             Self::Run(s) => Box::new(s),
+            Self::Schema(s) => Box::new(s),
+            Self::Validate(s) => Box::new(s),
+            Self::Watch(s) => Box::new(s),
         }
     }
 }
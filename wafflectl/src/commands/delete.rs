@@ -2,9 +2,17 @@ use super::*;
 use crate::http::service_path;
 use serde::Serialize;
 
-// wafflectl delete <lease {id} {service}|service {name}>
+// wafflectl delete <job {id}|lease {id} {service}|service {name}>
 #[derive(Debug, StructOpt)]
 pub enum Delete {
+    /// Cancel a job
+    ///
+    /// Removes a job from the queue before it starts running.
+    /// Jobs that are already running cannot be cancelled.
+    Job {
+        /// The ID of the job
+        id: u64,
+    },
     /// Stop tracking a lease
     ///
     /// Removes a lease by its full ID from a particular service,
@@ -31,12 +39,15 @@ impl Subcommand for Delete {
     /// Handle the subcommand call
     fn execute(&self, client: Client) -> Result<Option<Table>> {
         match self {
+            Self::Job { id } => {
+                client.delete::<_, &str>(&["jobs", &id.to_string()], None)?;
+            }
             Self::Lease { id, service } => {
                 let params = Lease { id };
                 client.delete(&["leases", service.as_str()], Some(params))?;
             }
             Self::Service { name } => {
-                client.delete::<_, &str>(&service_path("services", &name), None)?;
+                client.delete::<_, &str>(&service_path("services", name), None)?;
             }
         }
 
@@ -0,0 +1,190 @@
+use super::*;
+use crate::http::service_action_path;
+use crossterm::terminal;
+use eyre::eyre;
+use serde::Serialize;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    process,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+use tungstenite::{client::AutoStream, stream::Stream, Message, WebSocket};
+
+type Socket = WebSocket<AutoStream>;
+
+/// Prefix for binary messages containing output from stderr
+const STDERR: u8 = 2;
+
+// wafflectl exec {name} [-t] -- {command}...
+#[derive(Debug, StructOpt)]
+pub struct Exec {
+    /// The name of the service
+    name: String,
+    /// Allocate an interactive TTY
+    #[structopt(short, long)]
+    tty: bool,
+    /// The command to run
+    #[structopt(last = true, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Request<'c> {
+    Start {
+        command: &'c [String],
+        tty: bool,
+        width: Option<u16>,
+        height: Option<u16>,
+    },
+    Resize {
+        width: u16,
+        height: u16,
+    },
+    Eof,
+}
+
+impl<'c> From<Request<'c>> for Message {
+    fn from(request: Request<'c>) -> Message {
+        Message::text(serde_json::to_string(&request).unwrap())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Response {
+    Exit { code: Option<i64> },
+    Error { message: String },
+}
+
+impl Subcommand for Exec {
+    /// Handle the subcommand call
+    fn execute(&self, client: Client) -> Result<Option<Table>> {
+        let path = service_action_path("services", &self.name, "exec");
+
+        let mut socket = client.websocket(&path)?;
+
+        let size = if self.tty {
+            terminal::size().ok()
+        } else {
+            None
+        };
+        socket.write_message(
+            Request::Start {
+                command: &self.command,
+                tty: self.tty,
+                width: size.map(|(w, _)| w),
+                height: size.map(|(_, h)| h),
+            }
+            .into(),
+        )?;
+
+        // Poll the socket so input can be sent while waiting for output
+        let stream = match socket.get_ref() {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => &stream.sock,
+        };
+        stream.set_read_timeout(Some(Duration::from_millis(25)))?;
+
+        if self.tty {
+            terminal::enable_raw_mode()?;
+        }
+        let result = bridge(&mut socket, size);
+        if self.tty {
+            terminal::disable_raw_mode()?;
+        }
+
+        match result? {
+            Some(0) => Ok(None),
+            Some(code) => process::exit(code as i32),
+            None => Err(eyre!("command exited without a status code")),
+        }
+    }
+}
+
+/// Forward stdin to the command and its output to stdout/stderr until it exits
+fn bridge(socket: &mut Socket, mut size: Option<(u16, u16)>) -> Result<Option<i64>> {
+    let input = read_stdin();
+    let mut input_open = true;
+
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+
+    loop {
+        while input_open {
+            match input.try_recv() {
+                Ok(Some(bytes)) => socket.write_message(Message::binary(bytes))?,
+                Ok(None) | Err(TryRecvError::Disconnected) => {
+                    socket.write_message(Request::Eof.into())?;
+                    input_open = false;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        // Keep the remote TTY the same size as the local terminal
+        if let Some(current) = size {
+            match terminal::size() {
+                Ok(new) if new != current => {
+                    let (width, height) = new;
+                    socket.write_message(Request::Resize { width, height }.into())?;
+                    size = Some(new);
+                }
+                _ => {}
+            }
+        }
+
+        match socket.read_message() {
+            Ok(Message::Binary(data)) => match data.split_first() {
+                Some((&STDERR, output)) => {
+                    stderr.write_all(output)?;
+                    stderr.flush()?;
+                }
+                Some((_, output)) => {
+                    stdout.write_all(output)?;
+                    stdout.flush()?;
+                }
+                None => {}
+            },
+            Ok(Message::Text(text)) => match serde_json::from_str(&text)? {
+                Response::Exit { code } => return Ok(code),
+                Response::Error { message } => return Err(eyre!(message)),
+            },
+            Ok(Message::Close(_)) => {
+                return Err(eyre!("connection closed before the command exited"))
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Read stdin in the background, sending `None` once it is closed
+fn read_stdin() -> Receiver<Option<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buffer = [0; 4096];
+
+        loop {
+            match stdin.read(&mut buffer) {
+                Ok(0) | Err(_) => {
+                    tx.send(None).ok();
+                    break;
+                }
+                Ok(n) => {
+                    if tx.send(Some(buffer[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    rx
+}
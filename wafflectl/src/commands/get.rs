@@ -1,9 +1,10 @@
 use super::*;
-use crate::http::service_path;
-use std::collections::HashMap;
+use crate::http::{service_action_path, service_path};
+use serde::Serialize;
+use std::{collections::HashMap, fmt::Display};
 use tabled::{Disable, Header};
 
-// wafflectl get <deployments|leases|services|service {name}>
+// wafflectl get <deployments|jobs|leases|services|service {name}|history {name}>
 #[derive(Debug, StructOpt)]
 pub enum Get {
     /// Get the most recently deployed version
@@ -11,6 +12,22 @@ pub enum Get {
     /// Gets the commit hash of the most recently deployed version
     /// and some simple statistics about the current services.
     Deployments,
+    /// Get the jobs processed by the workers
+    ///
+    /// Get the jobs that are waiting to run, running, or finished
+    /// recently, oldest first. Finished jobs are kept for the
+    /// configured retention period.
+    Jobs {
+        /// Only show jobs with the status (pending, running, done, failed, cancelled, or skipped)
+        #[structopt(long)]
+        status: Option<String>,
+        /// Only show jobs dispatched by the job with the ID
+        #[structopt(long)]
+        parent: Option<u64>,
+        /// Only show jobs for the service
+        #[structopt(long)]
+        service: Option<String>,
+    },
     /// Get all the currently registered leases
     ///
     /// Get a map of services to the leases that are currently
@@ -25,11 +42,20 @@ pub enum Get {
     /// Get details about a service
     ///
     /// Get the configuration for a service as WaffleMaker sees it
-    /// and the ID of the container running it (if any).
+    /// and the IDs of the containers running it (if any).
     Service {
         /// The name of the service
         name: String,
     },
+    /// Get the deployment history of a service
+    ///
+    /// Get the previous deployments of a service, most recent first,
+    /// and whether they succeeded. The version can be used to roll
+    /// back to a particular deployment.
+    History {
+        /// The name of the service
+        name: String,
+    },
 }
 
 impl Subcommand for Get {
@@ -40,9 +66,22 @@ impl Subcommand for Get {
                 let response: DeploymentsResponse = client.get(&["deployments"])?;
                 Table::new(&[response])
             }
+            Self::Jobs {
+                status,
+                parent,
+                service,
+            } => {
+                let query = JobsQuery {
+                    status: status.as_deref(),
+                    parent: *parent,
+                    service: service.as_deref(),
+                };
+                let response: Vec<Job> = client.get_query(&["jobs"], Some(query))?;
+                Table::new(response)
+            }
             Self::Leases => {
                 let response: LeasesResponse = client.get(&["leases"])?;
-                Table::new(&response.into_table())
+                Table::new(response.into_table())
             }
             Self::Services => {
                 let response: Vec<String> = client.get(&["services"])?;
@@ -51,9 +90,15 @@ impl Subcommand for Get {
                     .with(Disable::Row(1..=1))
             }
             Self::Service { name } => {
-                let response: Service = client.get(&service_path("services", &name))?;
+                let response: Service = client.get(&service_path("services", name))?;
                 Table::new(&[response])
             }
+            Self::History { name } => {
+                let path = service_action_path("services", name, "history");
+
+                let response: Vec<HistoryEntry> = client.get(&path)?;
+                Table::new(response)
+            }
         };
 
         Ok(Some(table))
@@ -67,6 +112,36 @@ struct DeploymentsResponse {
     running: u64,
 }
 
+#[derive(Serialize)]
+pub(super) struct JobsQuery<'q> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'q str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<&'q str>,
+}
+
+#[derive(Debug, Deserialize, Tabled)]
+pub(super) struct Job {
+    pub id: u64,
+    pub name: String,
+    #[field(display_with = "display_option")]
+    pub service: Option<String>,
+    pub status: String,
+    #[field(display_with = "display_option")]
+    pub parent: Option<u64>,
+    #[field(display_with = "display_option")]
+    pub worker: Option<u32>,
+    #[serde(default)]
+    pub retries: u32,
+    pub created_at: String,
+    #[field(display_with = "display_option")]
+    pub finished_at: Option<String>,
+    #[field(display_with = "display_option")]
+    pub error: Option<String>,
+}
+
 #[derive(Deserialize, Tabled)]
 struct Lease {
     #[serde(skip)]
@@ -79,7 +154,7 @@ struct Lease {
 #[derive(Deserialize)]
 struct LeasesResponse {
     leases: HashMap<String, Vec<Lease>>,
-    services: HashMap<String, String>,
+    services: HashMap<String, Vec<String>>,
 }
 
 impl LeasesResponse {
@@ -87,11 +162,13 @@ impl LeasesResponse {
     fn into_table(mut self) -> Vec<Lease> {
         let mut table = Vec::new();
 
-        for (service, container) in self.services {
-            if let Some(mut leases) = self.leases.remove(&container) {
-                while let Some(mut lease) = leases.pop() {
-                    lease.service = service.clone();
-                    table.push(lease);
+        for (service, containers) in self.services {
+            for container in containers {
+                if let Some(mut leases) = self.leases.remove(&container) {
+                    while let Some(mut lease) = leases.pop() {
+                        lease.service = service.clone();
+                        table.push(lease);
+                    }
                 }
             }
         }
@@ -106,10 +183,24 @@ struct Service {
     automatic_updates: bool,
     #[field(display_with = "display_option")]
     domain: Option<String>,
-    #[field(display_with = "display_option")]
-    deployment_id: Option<String>,
+    replicas: u32,
+    #[field(display_with = "display_list")]
+    deployment_ids: Vec<String>,
     #[header(inline("dependency."))]
     dependencies: ServiceDependencies,
+    #[header(inline("limit."))]
+    resources: ServiceResources,
+}
+
+#[derive(Debug, Deserialize, Tabled)]
+struct HistoryEntry {
+    version: u64,
+    timestamp: u64,
+    image: String,
+    commit: String,
+    #[field(display_with = "display_list")]
+    deployment_ids: Vec<String>,
+    outcome: String,
 }
 
 #[derive(Debug, Deserialize, Tabled)]
@@ -118,9 +209,50 @@ struct ServiceDependencies {
     redis: bool,
 }
 
-fn display_option(o: &Option<String>) -> String {
+#[derive(Debug, Deserialize, Tabled)]
+struct ServiceResources {
+    #[field(display_with = "display_bytes")]
+    memory: Option<u64>,
+    #[field(display_with = "display_bytes")]
+    memory_reservation: Option<u64>,
+    #[field(display_with = "display_option")]
+    cpu_shares: Option<i64>,
+    #[field(display_with = "display_option")]
+    cpu_period: Option<i64>,
+    #[field(display_with = "display_option")]
+    cpu_quota: Option<i64>,
+    #[field(display_with = "display_option")]
+    pids: Option<i64>,
+}
+
+fn display_bytes(o: &Option<u64>) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
     match o {
-        Some(s) => s.to_owned(),
+        Some(bytes) => {
+            let mut value = *bytes;
+            let mut unit = 0;
+            while value >= 1024 && value % 1024 == 0 && unit < UNITS.len() - 1 {
+                value /= 1024;
+                unit += 1;
+            }
+            format!("{}{}", value, UNITS[unit])
+        }
         None => "[none]".to_owned(),
     }
 }
+
+fn display_option<T: Display>(o: &Option<T>) -> String {
+    match o {
+        Some(s) => s.to_string(),
+        None => "[none]".to_owned(),
+    }
+}
+
+fn display_list(l: &[String]) -> String {
+    if l.is_empty() {
+        "[none]".to_owned()
+    } else {
+        l.join("\n")
+    }
+}
@@ -0,0 +1,46 @@
+use super::*;
+use crate::http::service_action_path;
+use serde::Serialize;
+use std::io;
+
+// wafflectl logs {name} [-f] [--tail {lines}] [--since {timestamp}]
+#[derive(Debug, StructOpt)]
+pub struct Logs {
+    /// The name of the service
+    name: String,
+    /// Keep streaming new output
+    #[structopt(short, long)]
+    follow: bool,
+    /// The number of lines to show from the end of the logs
+    #[structopt(long)]
+    tail: Option<u64>,
+    /// Only show output since the UNIX timestamp
+    #[structopt(long)]
+    since: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Query {
+    follow: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tail: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<i64>,
+}
+
+impl Subcommand for Logs {
+    /// Handle the subcommand call
+    fn execute(&self, client: Client) -> Result<Option<Table>> {
+        let path = service_action_path("services", &self.name, "logs");
+
+        let query = Query {
+            follow: self.follow,
+            tail: self.tail,
+            since: self.since,
+        };
+        let mut response = client.stream(&path, Some(query))?;
+        io::copy(&mut response, &mut io::stdout())?;
+
+        Ok(None)
+    }
+}
@@ -6,13 +6,25 @@ use tabled::{Table, Tabled};
 
 mod add;
 mod delete;
+mod exec;
 mod get;
+mod logs;
+mod plan;
 mod run;
+mod schema;
+mod validate;
+mod watch;
 
 pub use add::Add;
 pub use delete::Delete;
+pub use exec::Exec;
 pub use get::Get;
+pub use logs::Logs;
+pub use plan::Plan;
 pub use run::Run;
+pub use schema::Schema;
+pub use validate::Validate;
+pub use watch::Watch;
 
 pub trait Subcommand {
     fn execute(&self, client: Client) -> Result<Option<Table>>;
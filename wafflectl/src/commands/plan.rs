@@ -0,0 +1,77 @@
+use super::*;
+
+// wafflectl plan {before} {after}
+#[derive(Debug, StructOpt)]
+pub struct Plan {
+    /// The commit hash of the before state
+    before: String,
+    /// The commit hash of the after state
+    after: String,
+}
+
+#[derive(Deserialize)]
+struct PlanResponse {
+    services: Vec<Change>,
+    errors: Vec<ParseError>,
+}
+
+#[derive(Deserialize, Tabled)]
+struct Change {
+    name: String,
+    action: String,
+    #[field(display_with = "display_list")]
+    fields: Vec<String>,
+    #[header("new secrets")]
+    #[field(display_with = "display_list")]
+    secrets: Vec<String>,
+    #[field(display_with = "display_dns")]
+    dns: Vec<DnsChange>,
+}
+
+#[derive(Deserialize)]
+struct DnsChange {
+    action: String,
+    domain: String,
+}
+
+#[derive(Deserialize)]
+struct ParseError {
+    path: String,
+    message: String,
+}
+
+impl Subcommand for Plan {
+    /// Handle the subcommand call
+    fn execute(&self, client: Client) -> Result<Option<Table>> {
+        let query = [("before", &self.before), ("after", &self.after)];
+        let plan: PlanResponse = client.post_json(&["deployments", "plan"], Some(query))?;
+
+        for error in &plan.errors {
+            eprintln!("failed to parse {}: {}", error.path, error.message);
+        }
+
+        Ok(Some(Table::new(plan.services)))
+    }
+}
+
+fn display_list(l: &[String]) -> String {
+    if l.is_empty() {
+        "[none]".to_owned()
+    } else {
+        l.join("\n")
+    }
+}
+
+fn display_dns(l: &[DnsChange]) -> String {
+    if l.is_empty() {
+        return "[none]".to_owned();
+    }
+
+    l.iter()
+        .map(|change| match change.action.as_str() {
+            "add" => format!("+ {}", change.domain),
+            _ => format!("- {}", change.domain),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,7 +1,7 @@
 use super::*;
-use crate::http::service_path;
+use crate::http::{service_action_path, service_path};
 
-// wafflectl run <deployment {commit}|service {name}>
+// wafflectl run <deployment {commit}|service {name}|rollback {name}>
 #[derive(Debug, StructOpt)]
 pub enum Run {
     /// Run a deployment
@@ -19,6 +19,18 @@ pub enum Run {
         /// The name of the service
         name: String,
     },
+    /// Roll back a service to a previous deployment
+    ///
+    /// Re-deploy the configuration of a previous version from the
+    /// service's history. Defaults to the most recent successful
+    /// deployment before the current one.
+    Rollback {
+        /// The name of the service
+        name: String,
+        /// The version to roll back to
+        #[structopt(long)]
+        to: Option<u64>,
+    },
 }
 
 impl Subcommand for Run {
@@ -29,7 +41,12 @@ impl Subcommand for Run {
                 client.put::<&str, _>(&["deployments", before.as_str()], None)?;
             }
             Self::Service { name } => {
-                client.put::<&str, _>(&service_path("services", &name), None)?;
+                client.put::<&str, _>(&service_path("services", name), None)?;
+            }
+            Self::Rollback { name, to } => {
+                let path = service_action_path("services", name, "rollback");
+
+                client.post(&path, to.map(|to| [("to", to)]))?;
             }
         }
 
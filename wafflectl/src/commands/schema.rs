@@ -0,0 +1,16 @@
+use super::*;
+use wafflemaker::service;
+
+// wafflectl schema
+#[derive(Debug, StructOpt)]
+pub struct Schema {}
+
+impl Subcommand for Schema {
+    /// Handle the subcommand call
+    fn execute(&self, _client: Client) -> Result<Option<Table>> {
+        let schema = serde_json::to_string_pretty(&service::schema())?;
+        println!("{}", schema);
+
+        Ok(None)
+    }
+}
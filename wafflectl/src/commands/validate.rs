@@ -0,0 +1,111 @@
+use super::*;
+use eyre::{eyre, WrapErr};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+use wafflemaker::service::{self, profile, Profile};
+
+// wafflectl validate {path}
+#[derive(Debug, StructOpt)]
+pub struct Validate {
+    /// The service file, or directory of service files, to check
+    ///
+    /// Directories are searched recursively for `.toml` files, skipping any hidden
+    /// directories. Included profiles are loaded from the `_profiles` directory at the root
+    /// of the repository. Nothing is sent to the server.
+    path: PathBuf,
+}
+
+impl Subcommand for Validate {
+    /// Handle the subcommand call
+    fn execute(&self, _client: Client) -> Result<Option<Table>> {
+        let mut files = Vec::new();
+        if self.path.is_dir() {
+            find_services(&self.path, &mut files)?;
+            files.sort();
+        } else {
+            files.push(self.path.clone());
+        }
+        let root = repository_root(&self.path);
+
+        let mut problems = 0;
+        for file in &files {
+            let raw =
+                fs::read(file).wrap_err_with(|| format!("failed to read {}", file.display()))?;
+
+            let issues = service::validate(&raw, |name| {
+                let path = root.join(Profile::path(name)?);
+                let raw = fs::read(&path).map_err(|e| {
+                    anyhow::anyhow!(
+                        "failed to read profile {:?} ({}): {}",
+                        name,
+                        path.display(),
+                        e
+                    )
+                })?;
+                Profile::from_slice(&raw)
+                    .map_err(|e| anyhow::anyhow!("failed to parse profile {:?}: {}", name, e))
+            });
+
+            for issue in issues {
+                problems += 1;
+                match issue.field.as_str() {
+                    "" => println!("{}: {}", file.display(), issue.message),
+                    field => println!("{}: {}: {}", file.display(), field, issue.message),
+                }
+            }
+        }
+
+        match problems {
+            0 => {
+                println!("checked {} service(s), no problems found", files.len());
+                Ok(None)
+            }
+            _ => Err(eyre!(
+                "found {} problem(s) in {} service(s)",
+                problems,
+                files.len()
+            )),
+        }
+    }
+}
+
+/// Find the root of the repository containing the path, this is the closest directory with
+/// a profiles directory, falling back to the path itself or its parent
+fn repository_root(path: &Path) -> PathBuf {
+    let start = match path.is_dir() {
+        true => path,
+        false => path.parent().unwrap_or_else(|| Path::new(".")),
+    };
+
+    start
+        .ancestors()
+        .find(|dir| dir.join(profile::DIRECTORY).is_dir())
+        .unwrap_or(start)
+        .to_path_buf()
+}
+
+/// Recursively find all the service files in a directory
+fn find_services(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).wrap_err_with(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.starts_with('.'));
+
+        let profiles = path.file_name().and_then(OsStr::to_str) == Some(profile::DIRECTORY);
+
+        if path.is_dir() {
+            if !hidden && !profiles {
+                find_services(&path, files)?;
+            }
+        } else if path.extension().and_then(OsStr::to_str) == Some("toml") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use super::{
+    get::{Job, JobsQuery},
+    *,
+};
+use eyre::eyre;
+use std::{collections::HashMap, thread, time::Duration};
+
+/// How often to check for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// wafflectl watch <job {id}>
+#[derive(Debug, StructOpt)]
+pub enum Watch {
+    /// Follow a job until it finishes
+    ///
+    /// Prints whenever the status of the job, or any of the jobs it
+    /// dispatched, changes. Exits with an error if any of them
+    /// failed, were cancelled, or were skipped.
+    Job {
+        /// The ID of the job
+        id: u64,
+    },
+}
+
+impl Subcommand for Watch {
+    /// Handle the subcommand call
+    fn execute(&self, client: Client) -> Result<Option<Table>> {
+        match self {
+            Self::Job { id } => watch_job(client, *id)?,
+        }
+
+        Ok(None)
+    }
+}
+
+/// Poll a job and its children until they have all finished
+fn watch_job(client: Client, id: u64) -> Result<()> {
+    let mut seen = HashMap::new();
+
+    loop {
+        let job: Job = client.clone().get(&["jobs", &id.to_string()])?;
+        let query = JobsQuery {
+            status: None,
+            parent: Some(id),
+            service: None,
+        };
+        let children: Vec<Job> = client.clone().get_query(&["jobs"], Some(query))?;
+
+        let mut finished = true;
+        for job in std::iter::once(&job).chain(&children) {
+            finished &= is_finished(&job.status);
+
+            if seen.get(&job.id) != Some(&job.status) {
+                print_status(job);
+                seen.insert(job.id, job.status.clone());
+            }
+        }
+
+        if finished {
+            let unsuccessful = std::iter::once(&job)
+                .chain(&children)
+                .filter(|job| job.status != "done")
+                .count();
+            return match unsuccessful {
+                0 => Ok(()),
+                _ => Err(eyre!("{} job(s) did not succeed", unsuccessful)),
+            };
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Print the current status of a job
+fn print_status(job: &Job) {
+    let mut line = format!("[{}] {}", job.id, job.name);
+    if let Some(service) = &job.service {
+        line.push_str(&format!(" {}", service));
+    }
+    line.push_str(&format!(": {}", job.status));
+    if let (Some(worker), "running") = (job.worker, job.status.as_str()) {
+        line.push_str(&format!(" on worker {}", worker));
+    }
+    if let Some(error) = &job.error {
+        line.push_str(&format!(" ({})", error));
+    }
+
+    println!("{}", line);
+}
+
+/// Whether the job will no longer change
+fn is_finished(status: &str) -> bool {
+    matches!(status, "done" | "failed" | "cancelled" | "skipped")
+}
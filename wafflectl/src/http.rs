@@ -1,38 +1,54 @@
 use eyre::Result;
 use reqwest::{
-    blocking::Client as HTTPClient,
+    blocking::{Client as HTTPClient, Response},
     header::{HeaderMap, HeaderValue, AUTHORIZATION},
 };
 use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+use tungstenite::{
+    client::{AutoStream, IntoClientRequest},
+    WebSocket,
+};
 use url::Url;
 
 /// A customized HTTP client
+#[derive(Clone)]
 pub struct Client {
     inner: HTTPClient,
+    streaming: HTTPClient,
+    authorization: HeaderValue,
     base: Url,
 }
 
 impl Client {
     /// Create a new HTTP client
     pub fn new(base: Url, token: &str) -> Result<Self> {
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", token))?;
         let headers = {
             let mut map = HeaderMap::new();
-            map.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", token))?,
-            );
+            map.insert(AUTHORIZATION, authorization.clone());
             map
         };
-        let inner = HTTPClient::builder()
-            .default_headers(headers)
-            .user_agent(concat!(
-                env!("CARGO_PKG_NAME"),
-                "/",
-                env!("CARGO_PKG_VERSION")
-            ))
-            .build()?;
-
-        Ok(Self { inner, base })
+        let builder = || {
+            HTTPClient::builder()
+                .default_headers(headers.clone())
+                .user_agent(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "/",
+                    env!("CARGO_PKG_VERSION")
+                ))
+        };
+        let inner = builder().build()?;
+
+        // Streamed responses can go quiet for a long time, so they should never time out
+        let streaming = builder().timeout(None::<Duration>).build()?;
+
+        Ok(Self {
+            inner,
+            streaming,
+            authorization,
+            base,
+        })
     }
 
     fn full_url<I>(&mut self, segments: I)
@@ -64,6 +80,68 @@ impl Client {
         Ok(response)
     }
 
+    /// Send a GET request with optional query parameters
+    pub fn get_query<I, Q, R>(mut self, path: I, query: Option<Q>) -> Result<R>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        Q: Serialize,
+        R: DeserializeOwned,
+    {
+        self.full_url(path);
+
+        let mut request = self.inner.get(self.base);
+        if let Some(query) = query {
+            request = request.query(&query);
+        }
+
+        let response = request.send()?.error_for_status()?.json()?;
+        Ok(response)
+    }
+
+    /// Send a GET request with optional query parameters, returning the response as it is
+    /// received
+    pub fn stream<I, Q>(mut self, path: I, query: Option<Q>) -> Result<Response>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        Q: Serialize,
+    {
+        self.full_url(path);
+
+        let mut request = self.streaming.get(self.base);
+        if let Some(query) = query {
+            request = request.query(&query);
+        }
+
+        Ok(request.send()?.error_for_status()?)
+    }
+
+    /// Open a WebSocket connection
+    pub fn websocket<I>(mut self, path: I) -> Result<WebSocket<AutoStream>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.full_url(path);
+
+        let scheme = match self.base.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        self.base
+            .set_scheme(scheme)
+            .map_err(|_| eyre::eyre!("invalid address scheme"))?;
+
+        let mut request = self.base.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, self.authorization);
+
+        let (socket, _) = tungstenite::connect(request)?;
+        Ok(socket)
+    }
+
     /// Send a PUT request with an optional body
     pub fn put<B, I>(mut self, path: I, body: Option<B>) -> Result<()>
     where
@@ -82,6 +160,43 @@ impl Client {
         Ok(())
     }
 
+    /// Send a POST request with optional query parameters
+    pub fn post<I, Q>(mut self, path: I, query: Option<Q>) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        Q: Serialize,
+    {
+        self.full_url(path);
+
+        let mut request = self.inner.post(self.base);
+        if let Some(query) = query {
+            request = request.query(&query);
+        }
+
+        request.send()?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Send a POST request with optional query parameters, parsing the response body
+    pub fn post_json<I, Q, R>(mut self, path: I, query: Option<Q>) -> Result<R>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        Q: Serialize,
+        R: DeserializeOwned,
+    {
+        self.full_url(path);
+
+        let mut request = self.inner.post(self.base);
+        if let Some(query) = query {
+            request = request.query(&query);
+        }
+
+        let response = request.send()?.error_for_status()?.json()?;
+        Ok(response)
+    }
+
     /// Send a DELETE request with optional query parameters
     pub fn delete<I, Q>(mut self, path: I, query: Option<Q>) -> Result<()>
     where
@@ -110,3 +225,12 @@ pub fn service_path<'s>(base: &'s str, service: &'s str) -> Vec<&'s str> {
 
     path
 }
+
+/// Get the path to an action on a service, actions are separated from the name by a `-` segment
+pub fn service_action_path<'s>(base: &'s str, service: &'s str, action: &'s str) -> Vec<&'s str> {
+    let mut path = service_path(base, service);
+    path.push("-");
+    path.push(action);
+
+    path
+}